@@ -1,4 +1,7 @@
-use ic_cdk::api::call::ManualReply;
+use ic_cdk::api::call::{
+    decode_int_arg, decode_nat_arg, encode_int_arg, encode_nat_arg, ManualReply,
+};
+use ic_cdk::export::candid::{decode_args, encode_args, Int, Nat};
 use ic_cdk_macros::query;
 
 #[query]
@@ -11,4 +14,44 @@ fn manual_reject() -> ManualReply<u64> {
     ManualReply::reject("manual reject")
 }
 
+/// Returns the instructions taken to encode and decode a `nat` argument
+/// with the fast path and with the generic tuple path.
+#[query]
+fn nat_codec_instructions() -> (u64, u64) {
+    let n = Nat::from(u64::MAX);
+    let fast = instructions(|| {
+        let bytes = encode_nat_arg(&n);
+        decode_nat_arg(&bytes).unwrap();
+    });
+    let generic = instructions(|| {
+        let bytes = encode_args((n.clone(),)).unwrap();
+        let (_,): (Nat,) = decode_args(&bytes).unwrap();
+    });
+    (fast, generic)
+}
+
+/// Like `nat_codec_instructions`, for an `int` argument.
+#[query]
+fn int_codec_instructions() -> (u64, u64) {
+    let i = Int::from(i64::MIN);
+    let fast = instructions(|| {
+        let bytes = encode_int_arg(&i);
+        decode_int_arg(&bytes).unwrap();
+    });
+    let generic = instructions(|| {
+        let bytes = encode_args((i.clone(),)).unwrap();
+        let (_,): (Int,) = decode_args(&bytes).unwrap();
+    });
+    (fast, generic)
+}
+
+// The instructions taken to run `f` a hundred times.
+fn instructions(f: impl Fn()) -> u64 {
+    let start = ic_cdk::api::instruction_counter();
+    for _ in 0..100 {
+        f();
+    }
+    ic_cdk::api::instruction_counter() - start
+}
+
 fn main() {}
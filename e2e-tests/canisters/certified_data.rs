@@ -0,0 +1,44 @@
+use ic_cdk_macros::{init, query, update};
+use ic_certified_map::{CertifiedData, RbTree};
+use serde_bytes::ByteBuf;
+use std::cell::RefCell;
+
+type Tree = RbTree<Vec<u8>, Vec<u8>>;
+
+thread_local! {
+    static DATA: RefCell<CertifiedData<Tree>> = RefCell::default();
+}
+
+#[init]
+fn init() {
+    DATA.with(|data| data.borrow().certify());
+}
+
+#[update]
+fn insert(key: ByteBuf, value: ByteBuf) {
+    DATA.with(|data| {
+        data.borrow_mut()
+            .modify(|tree| tree.insert(key.into_vec(), value.into_vec()))
+    });
+}
+
+/// Returns the value along with the data certificate and the CBOR-encoded witness.
+#[query]
+fn lookup(key: ByteBuf) -> (Option<ByteBuf>, ByteBuf, ByteBuf) {
+    DATA.with(|data| {
+        let data = data.borrow();
+        let value = data.get().get(&key).cloned().map(ByteBuf::from);
+        let certified = data
+            .witness(|tree| tree.witness(&key))
+            .unwrap_or_else(|| ic_cdk::trap("no data certificate available"));
+        let tree = serde_cbor::to_vec(&certified.tree)
+            .unwrap_or_else(|e| ic_cdk::trap(&format!("failed to encode witness: {}", e)));
+        (
+            value,
+            ByteBuf::from(certified.certificate),
+            ByteBuf::from(tree),
+        )
+    })
+}
+
+fn main() {}
@@ -0,0 +1,124 @@
+use ic_cdk::api::call::call_raw;
+use ic_cdk::api::management_canister::main::{
+    canister_exists, canister_status, clear_chunk_store, install_chunked_code, stored_chunks,
+    update_settings, upload_chunk, CanisterIdRecord, CanisterInstallMode, CanisterSettings,
+    ChunkHash, InstallChunkedCodeArgument, UpdateSettingsArgument, UploadChunkArgument,
+};
+use ic_cdk::api::management_canister::provisional::{
+    provisional_create_canister_with_cycles, ProvisionalCreateCanisterWithCyclesArgument,
+};
+use ic_cdk::export::candid::Nat;
+use ic_cdk::export::Principal;
+use ic_cdk_macros::update;
+
+async fn create_canister() -> Principal {
+    let arg = ProvisionalCreateCanisterWithCyclesArgument {
+        amount: Some(1_000_000_000_000u64.into()),
+        settings: None,
+    };
+    provisional_create_canister_with_cycles(arg)
+        .await
+        .unwrap()
+        .0
+        .canister_id
+}
+
+/// Creates a canister, updates its freezing threshold and returns the
+/// freezing threshold reported by `canister_status`.
+#[update]
+async fn update_freezing_threshold(freezing_threshold: Nat) -> Nat {
+    let canister_id = create_canister().await;
+
+    let arg = UpdateSettingsArgument {
+        canister_id,
+        settings: CanisterSettings {
+            freezing_threshold: Some(freezing_threshold),
+            ..Default::default()
+        },
+    };
+    update_settings(arg).await.unwrap();
+
+    canister_status(CanisterIdRecord { canister_id })
+        .await
+        .unwrap()
+        .0
+        .settings
+        .freezing_threshold
+}
+
+/// Checks whether a newly created canister and the given one exist.
+#[update]
+async fn created_and_other_canister_exist(other: Principal) -> (bool, bool) {
+    let canister_id = create_canister().await;
+    (
+        canister_exists(canister_id).await.unwrap(),
+        canister_exists(other).await.unwrap(),
+    )
+}
+
+/// Creates a canister to install chunked code into.
+#[update]
+async fn create_chunk_target() -> Principal {
+    create_canister().await
+}
+
+/// Uploads a chunk to the chunk store of `canister_id` and returns its hash.
+#[update]
+async fn upload_chunk_to(canister_id: Principal, chunk: Vec<u8>) -> Vec<u8> {
+    upload_chunk(UploadChunkArgument { canister_id, chunk })
+        .await
+        .unwrap()
+        .0
+        .hash
+}
+
+/// Returns the hashes of the chunks in the chunk store of `canister_id`.
+#[update]
+async fn stored_chunk_hashes(canister_id: Principal) -> Vec<Vec<u8>> {
+    stored_chunks(CanisterIdRecord { canister_id })
+        .await
+        .unwrap()
+        .0
+        .into_iter()
+        .map(|chunk| chunk.hash)
+        .collect()
+}
+
+/// Installs the module made of the given chunks into `canister_id`, clears
+/// its chunk store, and returns the reply of the installed canister's
+/// `reverse` method to `[1, 2, 3]` and the number of chunks left in the
+/// store.
+#[update]
+async fn install_chunks_and_call(
+    canister_id: Principal,
+    chunk_hashes: Vec<Vec<u8>>,
+    wasm_module_hash: Vec<u8>,
+) -> (Vec<u8>, u64) {
+    install_chunked_code(InstallChunkedCodeArgument {
+        mode: CanisterInstallMode::Install,
+        target_canister: canister_id,
+        store_canister: None,
+        chunk_hashes_list: chunk_hashes
+            .into_iter()
+            .map(|hash| ChunkHash { hash })
+            .collect(),
+        wasm_module_hash,
+        arg: vec![],
+    })
+    .await
+    .unwrap();
+    clear_chunk_store(CanisterIdRecord { canister_id })
+        .await
+        .unwrap();
+    let reply = call_raw(canister_id, "reverse", &[1, 2, 3], 0)
+        .await
+        .unwrap();
+    let remaining = stored_chunks(CanisterIdRecord { canister_id })
+        .await
+        .unwrap()
+        .0
+        .len();
+    (reply, remaining as u64)
+}
+
+fn main() {}
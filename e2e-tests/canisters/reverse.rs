@@ -1,4 +1,5 @@
-use ic_cdk::api::call::{arg_data_raw, arg_data_raw_size, reply_raw};
+use ic_cdk::api::call::{arg_data_raw, arg_data_raw_size, reply_raw, ChunkedReplyWriter};
+use std::io::Write;
 
 #[export_name = "canister_query reverse"]
 fn reverse() {
@@ -11,4 +12,16 @@ fn empty_call() {
     reply_raw(&[]);
 }
 
+#[export_name = "canister_query reverse_chunked"]
+fn reverse_chunked() {
+    let arg_bytes: Vec<u8> = arg_data_raw();
+    let mut writer = ChunkedReplyWriter::new();
+    for chunk in arg_bytes.rchunks(2) {
+        let reversed: Vec<u8> = chunk.iter().rev().copied().collect();
+        writer.write_all(&reversed).unwrap();
+    }
+    assert_eq!(writer.bytes_written(), arg_bytes.len());
+    writer.finish();
+}
+
 fn main() {}
@@ -1,10 +1,12 @@
 // use ic_cdk::export::candid::utils::{decode_args, encode_args, ArgumentDecoder, ArgumentEncoder};
 // use ic_cdk::export::candid::Encode;
 use candid_legecy::utils::{decode_args, encode_args, ArgumentDecoder, ArgumentEncoder};
-use candid_legecy::Encode;
+use candid_legecy::{Encode, Nat};
 use ic_cdk_e2e_tests::cargo_build_canister;
 use ic_state_machine_tests::{CanisterId, ErrorCode, StateMachine, UserError, WasmResult};
 use serde_bytes::ByteBuf;
+use serde_cbor::Value;
+use sha2::{Digest, Sha256};
 
 #[derive(Debug)]
 enum CallError {
@@ -135,6 +137,11 @@ fn test_raw_api() {
     let result = env.query(canister_id, "reverse", vec![1, 2, 3, 4]).unwrap();
     assert_eq!(result, WasmResult::Reply(vec![4, 3, 2, 1]));
 
+    let result = env
+        .query(canister_id, "reverse_chunked", vec![1, 2, 3, 4, 5])
+        .unwrap();
+    assert_eq!(result, WasmResult::Reply(vec![5, 4, 3, 2, 1]));
+
     let result = env
         .execute_ingress(canister_id, "empty_call", Default::default())
         .unwrap();
@@ -180,3 +187,222 @@ fn test_api_call() {
         .unwrap();
     assert_eq!(result, WasmResult::Reject("manual reject".to_string()));
 }
+
+#[test]
+fn test_nat_int_fast_paths() {
+    let env = StateMachine::new();
+    let rev = cargo_build_canister("api-call");
+    let canister_id = env.install_canister(rev, vec![], None).unwrap();
+
+    for method in ["nat_codec_instructions", "int_codec_instructions"] {
+        let (fast, generic): (u64, u64) = query_candid(&env, canister_id, method, ())
+            .unwrap_or_else(|e| panic!("failed to query {}: {:?}", method, e));
+        println!(
+            "{}: {} instructions with the fast path, {} with the generic path",
+            method, fast, generic
+        );
+        assert!(
+            fast < generic,
+            "the fast path of {} isn't faster than the generic path",
+            method
+        );
+    }
+}
+
+fn hash_with_domain(domain: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let mut h = Sha256::new();
+    h.update(&[domain.len() as u8]);
+    h.update(domain.as_bytes());
+    for part in parts {
+        h.update(part);
+    }
+    h.finalize().into()
+}
+
+/// Computes the root hash of a CBOR-encoded hash tree.
+fn reconstruct(tree: &Value) -> [u8; 32] {
+    match tree {
+        Value::Array(nodes) => match (&nodes[0], &nodes[1..]) {
+            (Value::Integer(0), []) => hash_with_domain("ic-hashtree-empty", &[]),
+            (Value::Integer(1), [l, r]) => {
+                hash_with_domain("ic-hashtree-fork", &[&reconstruct(l), &reconstruct(r)])
+            }
+            (Value::Integer(2), [Value::Bytes(label), t]) => {
+                hash_with_domain("ic-hashtree-labeled", &[label.as_slice(), &reconstruct(t)])
+            }
+            (Value::Integer(3), [Value::Bytes(data)]) => {
+                hash_with_domain("ic-hashtree-leaf", &[data.as_slice()])
+            }
+            (Value::Integer(4), [Value::Bytes(digest)]) => digest.as_slice().try_into().unwrap(),
+            _ => panic!("malformed hash tree node: {:?}", nodes),
+        },
+        _ => panic!("malformed hash tree: {:?}", tree),
+    }
+}
+
+/// Returns the leaf found at the given path of a CBOR-encoded hash tree.
+fn lookup_path<'a>(tree: &'a Value, path: &[&[u8]]) -> Option<&'a [u8]> {
+    let nodes = match tree {
+        Value::Array(nodes) => nodes,
+        _ => panic!("malformed hash tree: {:?}", tree),
+    };
+    match (&nodes[0], &nodes[1..], path.split_first()) {
+        (Value::Integer(1), [l, r], Some(_)) => {
+            lookup_path(l, path).or_else(|| lookup_path(r, path))
+        }
+        (Value::Integer(2), [Value::Bytes(label), t], Some((first, rest))) if label == first => {
+            lookup_path(t, rest)
+        }
+        (Value::Integer(3), [Value::Bytes(data)], None) => Some(data.as_slice()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_certified_data() {
+    let env = StateMachine::new();
+    let wasm = cargo_build_canister("certified-data");
+    let canister_id = env.install_canister(wasm, vec![], None).unwrap();
+
+    let () = call_candid(
+        &env,
+        canister_id,
+        "insert",
+        (
+            ByteBuf::from(b"key".to_vec()),
+            ByteBuf::from(b"value".to_vec()),
+        ),
+    )
+    .expect("failed to insert 'key'");
+
+    let (value, certificate, witness): (Option<ByteBuf>, ByteBuf, ByteBuf) = query_candid(
+        &env,
+        canister_id,
+        "lookup",
+        (ByteBuf::from(b"key".to_vec()),),
+    )
+    .expect("failed to lookup 'key'");
+    assert_eq!(value, Some(ByteBuf::from(b"value".to_vec())));
+
+    let witness: Value = serde_cbor::from_slice(&witness).expect("failed to decode witness");
+    assert_eq!(lookup_path(&witness, &[b"key"]), Some(&b"value"[..]));
+
+    let certificate: Value =
+        serde_cbor::from_slice(&certificate).expect("failed to decode certificate");
+    let certificate_tree = match &certificate {
+        Value::Map(fields) => &fields[&Value::Text("tree".to_string())],
+        _ => panic!("malformed certificate: {:?}", certificate),
+    };
+    let certified_data = lookup_path(
+        certificate_tree,
+        &[b"canister", canister_id.get().as_slice(), b"certified_data"],
+    )
+    .expect("certified data is missing from the certificate");
+    assert_eq!(certified_data, &reconstruct(&witness)[..]);
+}
+
+#[test]
+fn test_update_settings() {
+    let env = StateMachine::new();
+    let wasm = cargo_build_canister("management-caller");
+    let canister_id = env.install_canister(wasm, vec![], None).unwrap();
+
+    let (freezing_threshold,): (Nat,) = call_candid(
+        &env,
+        canister_id,
+        "update_freezing_threshold",
+        (Nat::from(10_000u64),),
+    )
+    .expect("failed to call 'update_freezing_threshold'");
+    assert_eq!(freezing_threshold, Nat::from(10_000u64));
+}
+
+#[test]
+fn test_canister_exists() {
+    let env = StateMachine::new();
+    let wasm = cargo_build_canister("management-caller");
+    let canister_id = env.install_canister(wasm, vec![], None).unwrap();
+
+    let (created, other): (bool, bool) = call_candid(
+        &env,
+        canister_id,
+        "created_and_other_canister_exist",
+        (candid_legecy::Principal::from_slice(&[0xff; 10]),),
+    )
+    .expect("failed to call 'created_and_other_canister_exist'");
+    assert!(created);
+    assert!(!other);
+}
+
+/// Appends a custom section of `padding` zero bytes to a WebAssembly module.
+fn pad_wasm(mut module: Vec<u8>, padding: usize) -> Vec<u8> {
+    fn leb128(mut n: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    let name = b"padding";
+    let mut section = leb128(name.len());
+    section.extend_from_slice(name);
+    section.resize(section.len() + padding, 0);
+    module.push(0);
+    module.extend(leb128(section.len()));
+    module.extend(section);
+    module
+}
+
+/// Checks that a module too large to be installed in a single message can be
+/// uploaded in chunks and installed with `install_chunked_code`.
+#[test]
+fn test_install_chunked_code() {
+    let env = StateMachine::new();
+    let wasm = cargo_build_canister("management-caller");
+    let canister_id = env.install_canister(wasm, vec![], None).unwrap();
+
+    let module = pad_wasm(cargo_build_canister("reverse"), 5 << 19);
+    assert!(module.len() > 2 << 20);
+
+    let (target,): (candid_legecy::Principal,) =
+        call_candid(&env, canister_id, "create_chunk_target", ())
+            .expect("failed to call 'create_chunk_target'");
+
+    let mut chunk_hashes = Vec::new();
+    for chunk in module.chunks(1 << 20) {
+        let (hash,): (ByteBuf,) = call_candid(
+            &env,
+            canister_id,
+            "upload_chunk_to",
+            (target, ByteBuf::from(chunk.to_vec())),
+        )
+        .expect("failed to call 'upload_chunk_to'");
+        assert_eq!(hash.as_slice(), Sha256::digest(chunk).as_slice());
+        chunk_hashes.push(hash);
+    }
+
+    let (mut stored,): (Vec<ByteBuf>,) =
+        call_candid(&env, canister_id, "stored_chunk_hashes", (target,))
+            .expect("failed to call 'stored_chunk_hashes'");
+    let mut expected = chunk_hashes.clone();
+    stored.sort();
+    expected.sort();
+    assert_eq!(stored, expected);
+
+    let wasm_module_hash = ByteBuf::from(Sha256::digest(&module).to_vec());
+    let (reply, remaining): (ByteBuf, u64) = call_candid(
+        &env,
+        canister_id,
+        "install_chunks_and_call",
+        (target, chunk_hashes, wasm_module_hash),
+    )
+    .expect("failed to call 'install_chunks_and_call'");
+    assert_eq!(reply.as_slice(), &[3, 2, 1]);
+    assert_eq!(remaining, 0);
+}
@@ -0,0 +1,159 @@
+use crate::hashtree::HashTree;
+use crate::rbtree::AsHashTree;
+use serde::Serialize;
+use std::cell::Cell;
+
+/// A hash tree stored in the canister heap whose root hash is kept in
+/// sync with the certified data of the canister.
+///
+/// Every mutation made through [CertifiedData::modify] recomputes the
+/// root hash and passes it to [ic_cdk::api::set_certified_data].
+/// Query calls can then use [CertifiedData::witness] to get the data
+/// certificate together with a witness for the requested data.
+///
+/// Requires the `ic-cdk` feature.
+///
+/// ```no_run
+/// use ic_certified_map::{CertifiedData, RbTree};
+/// use std::cell::RefCell;
+///
+/// thread_local! {
+///     static DATA: RefCell<CertifiedData<RbTree<Vec<u8>, Vec<u8>>>> = RefCell::default();
+/// }
+///
+/// fn insert(key: Vec<u8>, value: Vec<u8>) {
+///     DATA.with(|d| d.borrow_mut().modify(|tree| tree.insert(key, value)));
+/// }
+///
+/// fn lookup(key: Vec<u8>) -> Option<Vec<u8>> {
+///     DATA.with(|d| {
+///         let d = d.borrow();
+///         let certified = d.witness(|tree| tree.witness(&key))?;
+///         Some(serde_cbor::to_vec(&certified).unwrap())
+///     })
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CertifiedData<T> {
+    tree: T,
+    certified_at: Cell<u64>,
+}
+
+impl<T> CertifiedData<T> {
+    /// Wraps the given tree.
+    ///
+    /// Note that this does not certify the tree, as certified data can't
+    /// be set from a query context. Call [CertifiedData::certify] from
+    /// `canister_init` or an update call to do that.
+    pub const fn new(tree: T) -> Self {
+        Self {
+            tree,
+            certified_at: Cell::new(0),
+        }
+    }
+
+    /// Returns a reference to the certified tree.
+    pub fn get(&self) -> &T {
+        &self.tree
+    }
+
+    /// Returns the time at which [CertifiedData::certify] was last called,
+    /// in nanoseconds since the UNIX epoch, or 0 if it never was.
+    pub fn certified_at(&self) -> u64 {
+        self.certified_at.get()
+    }
+
+    /// Unwraps the certified tree.
+    pub fn into_inner(self) -> T {
+        self.tree
+    }
+}
+
+impl<T: AsHashTree> CertifiedData<T> {
+    /// Sets the certified data of the canister to the root hash of the tree.
+    ///
+    /// # Panics
+    ///
+    /// This function traps if it's called from an illegal context
+    /// (see [ic_cdk::api::set_certified_data]).
+    pub fn certify(&self) {
+        ic_cdk::api::set_certified_data(&self.tree.root_hash());
+        self.certified_at.set(ic_cdk::api::time());
+    }
+
+    /// Modifies the tree and certifies the new root hash.
+    pub fn modify<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        let result = f(&mut self.tree);
+        self.certify();
+        result
+    }
+
+    /// Combines the data certificate of the current call with a witness
+    /// built by `f`.
+    ///
+    /// Returns None if called not from a query call, as the data
+    /// certificate is only available there.
+    pub fn witness<'a>(
+        &'a self,
+        f: impl FnOnce(&'a T) -> HashTree<'a>,
+    ) -> Option<CertifiedWitness<'a>> {
+        let certificate = ic_cdk::api::data_certificate()?;
+        Some(CertifiedWitness {
+            certificate,
+            tree: f(&self.tree),
+        })
+    }
+
+    /// Bundles `value` with the time of the last certification, the data
+    /// certificate of the current call and a witness built by `f`.
+    ///
+    /// Returns None if called not from a query call, as the data
+    /// certificate is only available there.
+    pub fn response<'a, V>(
+        &'a self,
+        value: V,
+        f: impl FnOnce(&'a T) -> HashTree<'a>,
+    ) -> Option<CertifiedResponse<'a, V>> {
+        let CertifiedWitness { certificate, tree } = self.witness(f)?;
+        Some(CertifiedResponse {
+            value,
+            certified_at: self.certified_at.get(),
+            certificate,
+            tree,
+        })
+    }
+}
+
+/// A data certificate together with a witness of the certified data.
+///
+/// The root hash of `tree` equals the certified data authenticated by
+/// `certificate`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertifiedWitness<'a> {
+    /// The data certificate returned by [ic_cdk::api::data_certificate].
+    #[serde(with = "serde_bytes")]
+    pub certificate: Vec<u8>,
+    /// The witness of the requested data.
+    pub tree: HashTree<'a>,
+}
+
+/// A value served by a query together with what clients need to check that
+/// it's certified and fresh.
+///
+/// `certified_at` is the time at which the canister last set its certified
+/// data. It's informational only: it's not covered by the certificate, so
+/// clients should check freshness against the `/time` field of
+/// `certificate`, which is the certified time of the state the query ran on.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertifiedResponse<'a, T> {
+    /// The value returned by the query.
+    pub value: T,
+    /// The time of the last certification, in nanoseconds since the UNIX
+    /// epoch.
+    pub certified_at: u64,
+    /// The data certificate returned by [ic_cdk::api::data_certificate].
+    #[serde(with = "serde_bytes")]
+    pub certificate: Vec<u8>,
+    /// The witness of the value.
+    pub tree: HashTree<'a>,
+}
@@ -11,7 +11,7 @@ pub type Hash = [u8; 32];
 
 /// HashTree as defined in the interfaces spec.
 /// https://sdk.dfinity.org/docs/interface-spec/index.html#_certificate
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum HashTree<'a> {
     Empty,
     Fork(Box<(HashTree<'a>, HashTree<'a>)>),
@@ -20,6 +20,16 @@ pub enum HashTree<'a> {
     Pruned(Hash),
 }
 
+/// Constructs an empty hash tree.
+pub fn empty<'a>() -> HashTree<'a> {
+    HashTree::Empty
+}
+
+/// Constructs a leaf holding the given data.
+pub fn leaf<'a>(data: impl Into<Cow<'a, [u8]>>) -> HashTree<'a> {
+    HashTree::Leaf(data.into())
+}
+
 pub fn fork<'a>(l: HashTree<'a>, r: HashTree<'a>) -> HashTree<'a> {
     HashTree::Fork(Box::new((l, r)))
 }
@@ -48,7 +58,44 @@ pub fn labeled_hash(label: &[u8], content_hash: &Hash) -> Hash {
     h.finalize().into()
 }
 
-impl HashTree<'_> {
+impl<'a> HashTree<'a> {
+    /// Returns a witness for the given path of labels.
+    ///
+    /// The witness has the same root hash as the original tree, but all
+    /// the subtrees that are not on the path are replaced with their
+    /// hashes. The subtree found at the end of the path is kept intact.
+    ///
+    /// If the path does not exist in the tree, the whole tree is pruned.
+    /// Use [RbTree::witness](crate::RbTree::witness) if you need proofs
+    /// of absence.
+    pub fn witness(&self, path: &[&[u8]]) -> HashTree<'a> {
+        self.witness_path(path).0
+    }
+
+    // Returns the pruned tree and whether the first label of the path was
+    // found in this subtree.
+    fn witness_path(&self, path: &[&[u8]]) -> (HashTree<'a>, bool) {
+        let (label, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return (self.clone(), true),
+        };
+        match self {
+            Self::Empty => (Self::Empty, false),
+            Self::Fork(f) => {
+                let (l, l_found) = f.0.witness_path(path);
+                let (r, r_found) = f.1.witness_path(path);
+                if l_found || r_found {
+                    (fork(l, r), true)
+                } else {
+                    (Self::Pruned(self.reconstruct()), false)
+                }
+            }
+            Self::Labeled(l, t) if l == label => (labeled(*l, t.witness(rest)), true),
+            Self::Labeled(_, _) | Self::Leaf(_) => (Self::Pruned(self.reconstruct()), false),
+            Self::Pruned(h) => (Self::Pruned(*h), false),
+        }
+    }
+
     pub fn reconstruct(&self) -> Hash {
         match self {
             Self::Empty => domain_sep("ic-hashtree-empty").finalize().into(),
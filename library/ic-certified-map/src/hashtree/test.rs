@@ -1,6 +1,6 @@
 use super::{
-    fork, labeled,
-    HashTree::{Empty, Leaf},
+    empty, fork, labeled, leaf,
+    HashTree::{self, Empty, Leaf, Pruned},
 };
 use std::borrow::Cow;
 
@@ -38,3 +38,77 @@ fn test_public_spec_example() {
         hex::encode(serde_cbor::to_vec(&t).unwrap()),
         "8301830183024161830183018302417882034568656c6c6f810083024179820345776f726c6483024162820344676f6f648301830241638100830241648203476d6f726e696e67".to_string());
 }
+
+fn example_tree() -> HashTree<'static> {
+    fork(
+        fork(
+            labeled(
+                b"a",
+                fork(
+                    fork(labeled(b"x", leaf(&b"hello"[..])), empty()),
+                    labeled(b"y", leaf(&b"world"[..])),
+                ),
+            ),
+            labeled(b"b", leaf(&b"good"[..])),
+        ),
+        fork(labeled(b"c", empty()), labeled(b"d", leaf(&b"morning"[..]))),
+    )
+}
+
+#[test]
+fn test_witness_preserves_root_hash() {
+    let t = example_tree();
+    let paths: &[&[&[u8]]] = &[
+        &[],
+        &[b"a"],
+        &[b"a", b"x"],
+        &[b"a", b"y"],
+        &[b"b"],
+        &[b"d"],
+        &[b"e"],
+        &[b"a", b"z"],
+    ];
+    for path in paths {
+        assert_eq!(
+            t.witness(path).reconstruct(),
+            t.reconstruct(),
+            "witness for path {:?} has a different root hash",
+            path
+        );
+    }
+}
+
+#[test]
+fn test_witness_reveals_only_path() {
+    let t = example_tree();
+
+    //─┬─┬╴"a" ─┬─╴Pruned
+    // │ │      └╴"y" ─╴"world"
+    // │ └╴Pruned
+    // └╴Pruned
+    let expected = fork(
+        fork(
+            labeled(
+                b"a",
+                fork(
+                    Pruned(fork(labeled(b"x", leaf(&b"hello"[..])), empty()).reconstruct()),
+                    labeled(b"y", leaf(&b"world"[..])),
+                ),
+            ),
+            Pruned(labeled(b"b", leaf(&b"good"[..])).reconstruct()),
+        ),
+        Pruned(fork(labeled(b"c", empty()), labeled(b"d", leaf(&b"morning"[..]))).reconstruct()),
+    );
+
+    assert_eq!(
+        serde_cbor::to_vec(&t.witness(&[b"a", b"y"])).unwrap(),
+        serde_cbor::to_vec(&expected).unwrap()
+    );
+}
+
+#[test]
+fn test_witness_of_missing_path_is_pruned() {
+    let t = example_tree();
+    assert!(matches!(t.witness(&[b"e"]), Pruned(_)));
+    assert!(matches!(t.witness(&[b"c", b"x"]), HashTree::Fork(_)));
+}
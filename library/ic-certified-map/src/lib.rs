@@ -1,5 +1,9 @@
+#[cfg(feature = "ic-cdk")]
+mod certified_data;
 mod hashtree;
 mod rbtree;
 
+#[cfg(feature = "ic-cdk")]
+pub use crate::certified_data::*;
 pub use crate::hashtree::*;
 pub use crate::rbtree::*;
@@ -177,6 +177,113 @@ impl fmt::Display for AccountIdentifier {
     }
 }
 
+/// An account made of an owner and an optional subaccount, as used by the
+/// token standards (e.g. ICRC-1) to name the source and the destination of
+/// transfers.
+///
+/// A `None` subaccount refers to the same account as [DEFAULT_SUBACCOUNT].
+/// Accounts are displayed in the textual encoding of ICRC-1.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+impl Account {
+    /// Creates the default account of `owner`.
+    pub fn new(owner: Principal) -> Self {
+        Self {
+            owner,
+            subaccount: None,
+        }
+    }
+
+    /// Creates the account of `owner` with the given subaccount.
+    pub fn with_subaccount(owner: Principal, subaccount: Subaccount) -> Self {
+        Self {
+            owner,
+            subaccount: Some(subaccount),
+        }
+    }
+
+    /// Returns the subaccount, falling back to [DEFAULT_SUBACCOUNT].
+    pub fn effective_subaccount(&self) -> Subaccount {
+        self.subaccount.unwrap_or(DEFAULT_SUBACCOUNT)
+    }
+
+    /// Returns the identifier of this account on the ICP ledger.
+    pub fn account_identifier(&self) -> AccountIdentifier {
+        AccountIdentifier::new(&self.owner, &self.effective_subaccount())
+    }
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Self::new(owner)
+    }
+}
+
+// Accounts are compared by their effective subaccount, so that a missing
+// subaccount and the default one name the same account.
+impl PartialEq for Account {
+    fn eq(&self, other: &Self) -> bool {
+        self.owner == other.owner && self.effective_subaccount() == other.effective_subaccount()
+    }
+}
+
+impl Eq for Account {}
+
+impl std::hash::Hash for Account {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.owner.hash(state);
+        self.effective_subaccount().hash(state);
+    }
+}
+
+// Formats the account in the textual encoding of ICRC-1: the owner, then, if
+// the subaccount isn't the default one, the base32 CRC-32 of the owner and
+// the subaccount, and the subaccount in hex without its leading zeros.
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.subaccount {
+            Some(subaccount) if subaccount != DEFAULT_SUBACCOUNT => {
+                let mut hasher = crc32fast::Hasher::new();
+                hasher.update(self.owner.as_slice());
+                hasher.update(&subaccount.0);
+                let checksum = base32(&hasher.finalize().to_be_bytes());
+                let hex = hex::encode(subaccount.0);
+                write!(
+                    f,
+                    "{}-{}.{}",
+                    self.owner,
+                    checksum,
+                    hex.trim_start_matches('0')
+                )
+            }
+            _ => write!(f, "{}", self.owner),
+        }
+    }
+}
+
+// Encodes bytes in lowercase base32 (RFC 4648) without padding.
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
 /// Arguments for the `account_balance` call.
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct AccountBalanceArgs {
@@ -533,6 +640,50 @@ mod tests {
         assert!(AccountIdentifier::try_from(bytes).is_err());
     }
 
+    #[test]
+    fn test_account_default_subaccount() {
+        let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let account = Account::from(owner);
+        assert_eq!(account, Account::with_subaccount(owner, DEFAULT_SUBACCOUNT));
+        assert_eq!(
+            account.account_identifier(),
+            AccountIdentifier::new(&owner, &DEFAULT_SUBACCOUNT)
+        );
+        assert_eq!(account.to_string(), "rrkah-fqaaa-aaaaa-aaaaq-cai");
+    }
+
+    #[test]
+    fn test_account_textual_encoding() {
+        let owner =
+            Principal::from_text("k2t6j-2nvnp-4zjm3-25dtz-6xhaa-c7boj-5gayf-oj3xs-i43lp-teztq-6ae")
+                .unwrap();
+        let mut subaccount = [0; 32];
+        subaccount[31] = 1;
+        assert_eq!(
+            Account::with_subaccount(owner, Subaccount(subaccount)).to_string(),
+            "k2t6j-2nvnp-4zjm3-25dtz-6xhaa-c7boj-5gayf-oj3xs-i43lp-teztq-6ae-6cc627i.1"
+        );
+        let mut subaccount = [0; 32];
+        for (i, byte) in subaccount.iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        assert_eq!(
+            Account::with_subaccount(owner, Subaccount(subaccount)).to_string(),
+            "k2t6j-2nvnp-4zjm3-25dtz-6xhaa-c7boj-5gayf-oj3xs-i43lp-teztq-6ae-dfxgiyy.\
+             102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20"
+        );
+    }
+
+    #[test]
+    fn test_account_candid_roundtrip() {
+        let owner = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let account = Account::with_subaccount(owner, Subaccount([1; 32]));
+        let bytes = candid::encode_one(account).unwrap();
+        let decoded: Account = candid::decode_one(&bytes).unwrap();
+        assert_eq!(decoded.owner, owner);
+        assert_eq!(decoded.subaccount, Some(Subaccount([1; 32])));
+    }
+
     #[test]
     fn test_ledger_canister_id() {
         assert_eq!(
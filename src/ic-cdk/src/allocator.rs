@@ -0,0 +1,106 @@
+//! A global allocator wrapper tracking heap usage.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static BYTES_IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+/// A [GlobalAlloc] wrapper which counts the bytes allocated and freed by the
+/// wrapped allocator.
+///
+/// Install it with `#[global_allocator]` and read the counter with
+/// [heap_bytes_in_use]:
+///
+/// ```no_run
+/// #[global_allocator]
+/// static ALLOCATOR: ic_cdk::CountingAllocator = ic_cdk::CountingAllocator::new();
+///
+/// fn heap_size() -> usize {
+///     ic_cdk::heap_bytes_in_use()
+/// }
+/// ```
+///
+/// The overhead is one counter update per allocation, deallocation and
+/// reallocation, which is negligible compared to the cost of the
+/// allocation itself. The counter tracks the sizes requested by the
+/// program, not the memory reserved by the allocator, so it doesn't
+/// account for fragmentation or allocator metadata.
+#[derive(Debug, Default)]
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+
+impl CountingAllocator<System> {
+    /// Wraps the [System] allocator.
+    pub const fn new() -> Self {
+        Self { inner: System }
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Wraps the given allocator.
+    pub const fn with_allocator(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            BYTES_IN_USE.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        BYTES_IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            BYTES_IN_USE.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            BYTES_IN_USE.fetch_add(new_size, Ordering::Relaxed);
+            BYTES_IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// Returns the number of heap bytes currently allocated through a
+/// [CountingAllocator].
+///
+/// Always returns 0 if no [CountingAllocator] is installed.
+pub fn heap_bytes_in_use() -> usize {
+    BYTES_IN_USE.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_allocated_bytes() {
+        let allocator = CountingAllocator::new();
+        let before = heap_bytes_in_use();
+        unsafe {
+            let layout = Layout::from_size_align(100, 8).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert_eq!(heap_bytes_in_use(), before + 100);
+
+            let ptr = allocator.realloc(ptr, layout, 300);
+            assert_eq!(heap_bytes_in_use(), before + 300);
+
+            allocator.dealloc(ptr, Layout::from_size_align(300, 8).unwrap());
+            assert_eq!(heap_bytes_in_use(), before);
+        }
+    }
+}
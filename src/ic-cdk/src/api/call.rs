@@ -1,13 +1,19 @@
 //! APIs to make and manage calls in the canister.
+use crate::api::time::Timestamp;
 use crate::api::trap;
 use candid::utils::{ArgumentDecoder, ArgumentEncoder};
-use candid::{decode_args, encode_args, write_args, CandidType, Deserialize, Principal};
+use candid::{decode_args, encode_args, write_args, CandidType, Deserialize, Int, Nat, Principal};
 use serde::ser::Error;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::marker::PhantomData;
 use std::pin::Pin;
-use std::sync::atomic::Ordering;
 use std::task::{Context, Poll, Waker};
+use std::thread::LocalKey;
+use std::time::Duration;
 
 #[cfg(target_arch = "wasm32-unknown-unknown")]
 #[allow(dead_code)]
@@ -138,8 +144,13 @@ pub enum RejectionCode {
     DestinationInvalid = 3,
     CanisterReject = 4,
     CanisterError = 5,
+    /// The outcome of the call is unknown, e.g. because the deadline of a
+    /// best-effort call expired before a response arrived.
+    SysUnknown = 6,
 
-    Unknown,
+    /// A code this version doesn't know. It was 6 before `SysUnknown` took
+    /// that value.
+    Unknown = 7,
 }
 
 impl From<i32> for RejectionCode {
@@ -151,6 +162,7 @@ impl From<i32> for RejectionCode {
             3 => RejectionCode::DestinationInvalid,
             4 => RejectionCode::CanisterReject,
             5 => RejectionCode::CanisterError,
+            6 => RejectionCode::SysUnknown,
             _ => RejectionCode::Unknown,
         }
     }
@@ -162,24 +174,139 @@ impl From<u32> for RejectionCode {
     }
 }
 
+/// The reason why the system refused to send a call.
+///
+/// When `ic0.call_perform` fails, the call is not sent and none of its
+/// callbacks will run. Calls made through this module then resolve right
+/// away to an error with [CallPerformError::rejection_code] and the
+/// description of the error as the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallPerformError {
+    /// The system failed in a way retrying won't fix.
+    SysFatal,
+    /// The call could not be enqueued, for instance because the output
+    /// queue to the callee is full or the canister is low on cycles.
+    /// Retrying later may succeed.
+    SysTransient,
+    /// The callee doesn't exist.
+    DestinationInvalid,
+    /// A code not described by the interface specification.
+    Other(i32),
+}
+
+impl CallPerformError {
+    /// Returns the rejection code the failed call resolves to.
+    pub fn rejection_code(&self) -> RejectionCode {
+        match self {
+            Self::SysFatal => RejectionCode::SysFatal,
+            Self::SysTransient => RejectionCode::SysTransient,
+            Self::DestinationInvalid => RejectionCode::DestinationInvalid,
+            Self::Other(code) => RejectionCode::from(*code),
+        }
+    }
+}
+
+impl From<i32> for CallPerformError {
+    fn from(code: i32) -> Self {
+        match RejectionCode::from(code) {
+            RejectionCode::SysFatal => Self::SysFatal,
+            RejectionCode::SysTransient => Self::SysTransient,
+            RejectionCode::DestinationInvalid => Self::DestinationInvalid,
+            _ => Self::Other(code),
+        }
+    }
+}
+
+impl std::fmt::Display for CallPerformError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SysFatal => f.write_str("couldn't send message: fatal system error"),
+            Self::SysTransient => f.write_str(
+                "couldn't send message: the output queue is full or the canister is low on cycles, retry later",
+            ),
+            Self::DestinationInvalid => {
+                f.write_str("couldn't send message: the callee doesn't exist")
+            }
+            Self::Other(code) => write!(f, "couldn't send message: error code {}", code),
+        }
+    }
+}
+
+impl std::error::Error for CallPerformError {}
+
 /// The result of a Call.
 ///
 /// Errors on the IC have two components; a Code and a message associated with it.
 pub type CallResult<R> = Result<R, (RejectionCode, String)>;
 
+/// The error of a call made with [try_call].
+///
+/// Unlike [CallResult], it tells apart the failures which happen before
+/// the call is sent and after the reply is received from rejections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallError {
+    /// The arguments could not be encoded, the call was not sent.
+    Encode(String),
+    /// The call was rejected, either by the system or by the callee.
+    Reject(RejectionCode, String),
+    /// The deadline of the call expired before a response arrived.
+    ///
+    /// The callee may or may not have executed the call.
+    TimedOut(String),
+    /// The reply could not be decoded as the expected type.
+    Decode(String),
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Encode(msg) => write!(f, "failed to encode arguments: {}", msg),
+            Self::Reject(code, msg) => write!(f, "call rejected with code {:?}: {}", code, msg),
+            Self::TimedOut(msg) => write!(f, "call timed out: {}", msg),
+            Self::Decode(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+impl From<(RejectionCode, String)> for CallError {
+    fn from((code, msg): (RejectionCode, String)) -> Self {
+        match code {
+            RejectionCode::SysUnknown => Self::TimedOut(msg),
+            _ => Self::Reject(code, msg),
+        }
+    }
+}
+
+// Decodes the reply of a call in the reply callback, while the reply is still
+// in the message buffer.
+type ReplyDecoder<R> = Box<dyn FnOnce(&mut ArgDataReader) -> Result<R, String>>;
+
 // Internal state for the Future when sending a call.
-struct CallFutureState<R: serde::de::DeserializeOwned> {
+struct CallFutureState<R> {
+    decode: Option<ReplyDecoder<R>>,
     result: Option<CallResult<R>>,
     waker: Option<Waker>,
 }
 
-struct CallFuture<R: serde::de::DeserializeOwned> {
+impl<R> CallFutureState<R> {
+    fn new(decode: ReplyDecoder<R>) -> Self {
+        Self {
+            decode: Some(decode),
+            result: None,
+            waker: None,
+        }
+    }
+}
+
+struct CallFuture<R> {
     // We basically use Rc instead of Arc (since we're single threaded), and use
     // RefCell instead of Mutex (because we cannot lock in WASM).
     state: rc::WasmCell<CallFutureState<R>>,
 }
 
-impl<R: serde::de::DeserializeOwned> Future for CallFuture<R> {
+impl<R> Future for CallFuture<R> {
     type Output = Result<R, (RejectionCode, String)>;
 
     fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
@@ -196,19 +323,52 @@ impl<R: serde::de::DeserializeOwned> Future for CallFuture<R> {
     }
 }
 
+impl<R> CallFuture<R> {
+    // Whether the call was sent. A call refused before being sent, by the
+    // system or because of the limit of outstanding calls, is resolved
+    // right away.
+    fn is_sent(&self) -> bool {
+        self.state.borrow().result.is_none()
+    }
+}
+
+/// The callbacks keep their own reference to the state, so the reply of a
+/// call can arrive after its future was dropped, e.g. by
+/// [ic_select](crate::ic_select). The waker is cleared so that the
+/// callbacks don't wake the task awaiting the future, which may be gone.
+impl<R> Drop for CallFuture<R> {
+    fn drop(&mut self) {
+        self.state.borrow_mut().waker = None;
+    }
+}
+
 /// The callback from IC dereferences the future from a raw pointer, assigns the
 /// result and calls the waker. We cannot use a closure here because we pass raw
 /// pointers to the System and back.
-fn callback(state_ptr: *const InnerCell<CallFutureState<Vec<u8>>>) {
+fn callback<R>(state_ptr: *const InnerCell<CallFutureState<R>>) {
+    // The counter starts from zero after an upgrade, while the callbacks of
+    // the calls made before it may still run.
+    OUTSTANDING_CALLS.with(|n| n.set(n.get().saturating_sub(1)));
     let state = unsafe { WasmCell::from_raw(state_ptr) };
+    let decode = state.borrow_mut().decode.take();
+    let result = match (reject_code(), decode) {
+        (RejectionCode::NoError, Some(decode)) => {
+            decode(&mut ArgDataReader::new()).map_err(|err| (RejectionCode::CanisterError, err))
+        }
+        (RejectionCode::NoError, None) => unreachable!("the reply callback ran twice"),
+        (n, _) => Err((n, reject_message())),
+    };
+    complete(&state, result);
+}
+
+// Sets the result of a call and wakes the task awaiting it, if any.
+fn complete<R>(state: &WasmCell<CallFutureState<R>>, result: CallResult<R>) {
     // Make sure to un-borrow_mut the state.
-    {
-        state.borrow_mut().result = Some(match reject_code() {
-            RejectionCode::NoError => Ok(arg_data_raw()),
-            n => Err((n, reject_message())),
-        });
-    }
-    let w = state.borrow_mut().waker.take();
+    let w = {
+        let mut state = state.borrow_mut();
+        state.result = Some(result);
+        state.waker.take()
+    };
     if let Some(waker) = w {
         // This is all to protect this little guy here which will call the poll() which
         // borrow_mut() the state as well. So we need to be careful to not double-borrow_mut.
@@ -219,7 +379,9 @@ fn callback(state_ptr: *const InnerCell<CallFutureState<Vec<u8>>>) {
 /// This function is called when [callback] was just called with the same parameter, and trapped.
 /// We can't guarantee internal consistency at this point, but we can at least e.g. drop mutex guards.
 /// Waker is a very opaque API, so the best we can do is set a global flag and proceed normally.
-fn cleanup(state_ptr: *const InnerCell<CallFutureState<Vec<u8>>>) {
+fn cleanup<R>(state_ptr: *const InnerCell<CallFutureState<R>>) {
+    // The trap rolled back the decrement done in `callback`.
+    OUTSTANDING_CALLS.with(|n| n.set(n.get().saturating_sub(1)));
     let state = unsafe { WasmCell::from_raw(state_ptr) };
     // We set the call result, even though it won't be read on the
     // default executor, because we can't guarantee it was called on
@@ -234,9 +396,7 @@ fn cleanup(state_ptr: *const InnerCell<CallFutureState<Vec<u8>>>) {
     if let Some(waker) = w {
         // Flag that we do not want to actually wake the task - we
         // want to drop it *without* executing it.
-        crate::futures::CLEANUP.store(true, Ordering::Relaxed);
-        waker.wake();
-        crate::futures::CLEANUP.store(false, Ordering::Relaxed);
+        crate::futures::wake_for_cleanup(waker);
     }
 }
 
@@ -297,6 +457,7 @@ pub fn notify_raw(
     args_raw: &[u8],
     payment: u128,
 ) -> Result<(), RejectionCode> {
+    check_payment_allowed(payment);
     let callee = id.as_slice();
     // We set all callbacks to -1, which is guaranteed to be invalid callback index.
     // The system will still deliver the reply, but it will trap immediately because the callback
@@ -318,12 +479,41 @@ pub fn notify_raw(
         ic0::call_data_append(args_raw.as_ptr() as i32, args_raw.len() as i32);
         ic0::call_perform()
     };
+    notify_outcome(id, method, err_code)
+}
+
+// Reports the `call_perform` code of a notification to the observer, if
+// any, and turns it into the result of [notify_raw].
+fn notify_outcome(id: Principal, method: &str, err_code: i32) -> Result<(), RejectionCode> {
+    if let Some(observer) = NOTIFY_OBSERVER.with(|o| o.get()) {
+        let outcome = match err_code {
+            0 => Ok(()),
+            c => Err(CallPerformError::from(c)),
+        };
+        observer(id, method, outcome);
+    }
     match err_code {
         0 => Ok(()),
         c => Err(RejectionCode::from(c)),
     }
 }
 
+/// A function observing the notifications sent, see [set_notify_observer].
+///
+/// It gets the callee, the method and whether the system accepted to send
+/// the notification.
+pub type NotifyObserver = fn(Principal, &str, Result<(), CallPerformError>);
+
+/// Sets a function which runs after every notification is sent with
+/// [notify] and its variants.
+///
+/// As notifications get no reply, the outcome of sending them is all there
+/// is to observe, e.g. to count the notifications which the system refused
+/// to send. `None` (the default) removes the observer.
+pub fn set_notify_observer(observer: Option<NotifyObserver>) {
+    NOTIFY_OBSERVER.with(|o| o.set(observer));
+}
+
 /// Similar to `call`, but without serialization.
 pub fn call_raw(
     id: Principal,
@@ -331,6 +521,7 @@ pub fn call_raw(
     args_raw: &[u8],
     payment: u64,
 ) -> impl Future<Output = CallResult<Vec<u8>>> {
+    check_payment_allowed(payment as u128);
     call_raw_internal(id, method, args_raw, move || {
         if payment > 0 {
             unsafe {
@@ -347,57 +538,198 @@ pub fn call_raw128(
     args_raw: &[u8],
     payment: u128,
 ) -> impl Future<Output = CallResult<Vec<u8>>> {
+    check_payment_allowed(payment);
     call_raw_internal(id, method, args_raw, move || {
         add_payment(payment);
     })
 }
 
+/// Like [call], but also returns the size of the reply in bytes.
+///
+/// This is meant for accounting of the reply sizes, for instance to find
+/// the callees whose replies are the most expensive to decode. The size is
+/// read from the system in the reply callback, where the reply is decoded,
+/// so the raw reply isn't kept until the task resumes.
+pub fn call_with_reply_size<T, R>(
+    id: Principal,
+    method: &str,
+    args: T,
+) -> impl Future<Output = CallResult<(R, usize)>>
+where
+    T: ArgumentEncoder,
+    R: for<'a> ArgumentDecoder<'a> + 'static,
+{
+    let args_raw = encode_args(args).expect("Failed to encode arguments.");
+    let method_name = method.to_string();
+    let decode = move |_: &mut ArgDataReader| {
+        let size = arg_data_raw_size();
+        decode_args(&arg_data_raw())
+            .map(|reply| (reply, size))
+            .map_err(|err| decoder_error_to_reject::<R>(&id, &method_name, err).1)
+    };
+    call_internal(id, method, &args_raw, Box::new(decode), || {})
+}
+
+/// Like [call_raw128], but decodes the reply incrementally instead of
+/// copying it into a `Vec<u8>` first.
+///
+/// `decode` runs in the reply callback, where it reads the reply straight
+/// from the message buffer through an [ArgDataReader], so the peak heap
+/// usage is that of the decoded value plus whatever the deserializer
+/// buffers, rather than twice the size of the reply. This pays off for
+/// large replies in a format with a streaming deserializer over
+/// [std::io::Read], such as CBOR or JSON. Candid can't be decoded from a
+/// reader, so Candid replies gain nothing over [call].
+///
+/// An error returned by `decode` results in [RejectionCode::CanisterError]
+/// with the returned message. A panic in `decode` traps the callback, and
+/// the call is then dropped like any other call whose callback trapped.
+pub fn call_with_reader<R: 'static>(
+    id: Principal,
+    method: &str,
+    args_raw: &[u8],
+    payment: u128,
+    decode: impl FnOnce(&mut ArgDataReader) -> Result<R, String> + 'static,
+) -> impl Future<Output = CallResult<R>> {
+    check_payment_allowed(payment);
+    call_internal(id, method, args_raw, Box::new(decode), move || {
+        add_payment(payment);
+    })
+}
+
+thread_local! {
+    static OUTSTANDING_CALLS: Cell<usize> = Cell::new(0);
+    static MAX_OUTSTANDING_CALLS: Cell<Option<usize>> = Cell::new(None);
+    static FORBID_CYCLES: Cell<bool> = Cell::new(false);
+    static NOTIFY_OBSERVER: Cell<Option<NotifyObserver>> = Cell::new(None);
+}
+
+/// Forbids attaching cycles to outgoing calls.
+///
+/// Canisters which are never supposed to pay for the calls they make, such
+/// as read-only aggregators, can set this once in `init` and `post_upgrade`
+/// to guard against draining their balance by mistake. While forbidden, any
+/// call or notification with a non-zero payment panics, which traps the
+/// message execution before the call is sent. Calls without payment, such as
+/// the ones made with [call] or [notify], are not affected.
+pub fn set_forbid_cycles(forbid: bool) {
+    FORBID_CYCLES.with(|f| f.set(forbid));
+}
+
+fn check_payment_allowed(payment: u128) {
+    if payment > 0 && FORBID_CYCLES.with(|f| f.get()) {
+        panic!(
+            "attaching {} cycles to a call is forbidden by `set_forbid_cycles`",
+            payment
+        );
+    }
+}
+
+/// Returns the number of calls sent by this canister which haven't
+/// received a reply yet.
+///
+/// One-way calls sent with [notify] are not counted.
+pub fn outstanding_calls() -> usize {
+    OUTSTANDING_CALLS.with(|n| n.get())
+}
+
+/// Limits the number of outstanding calls, see [outstanding_calls].
+///
+/// Once the limit is reached, new calls are not sent and fail immediately
+/// with [RejectionCode::SysTransient] and the message "too many concurrent
+/// outgoing calls", instead of exhausting the queues of the canister.
+/// [call_idempotent] doesn't retry these calls. `None` (the default)
+/// removes the limit.
+pub fn set_max_outstanding_calls(max: Option<usize>) {
+    MAX_OUTSTANDING_CALLS.with(|m| m.set(max));
+}
+
+// The error of a call which isn't sent because the limit set with
+// [set_max_outstanding_calls] is reached.
+fn outstanding_call_limit_error() -> Option<(RejectionCode, String)> {
+    let max = MAX_OUTSTANDING_CALLS.with(|m| m.get())?;
+    (outstanding_calls() >= max).then(|| {
+        (
+            RejectionCode::SysTransient,
+            "too many concurrent outgoing calls".to_string(),
+        )
+    })
+}
+
 fn call_raw_internal(
     id: Principal,
     method: &str,
     args_raw: &[u8],
     payment_func: impl FnOnce(),
-) -> impl Future<Output = CallResult<Vec<u8>>> {
+) -> CallFuture<Vec<u8>> {
+    call_internal(
+        id,
+        method,
+        args_raw,
+        Box::new(|_| Ok(arg_data_raw())),
+        payment_func,
+    )
+}
+
+fn call_internal<R: 'static>(
+    id: Principal,
+    method: &str,
+    args_raw: &[u8],
+    decode: ReplyDecoder<R>,
+    payment_func: impl FnOnce(),
+) -> CallFuture<R> {
     let callee = id.as_slice();
-    let state = WasmCell::new(CallFutureState {
-        result: None,
-        waker: None,
-    });
+    let state = WasmCell::new(CallFutureState::new(decode));
+    if let Some(error) = outstanding_call_limit_error() {
+        state.borrow_mut().result = Some(Err(error));
+        return CallFuture { state };
+    }
     let state_ptr = WasmCell::into_raw(state.clone());
+    let reply_callback = callback::<R> as fn(_);
+    let reply_cleanup = cleanup::<R> as fn(_);
     let err_code = unsafe {
         ic0::call_new(
             callee.as_ptr() as i32,
             callee.len() as i32,
             method.as_ptr() as i32,
             method.len() as i32,
-            callback as usize as i32,
+            reply_callback as usize as i32,
             state_ptr as i32,
-            callback as usize as i32,
+            reply_callback as usize as i32,
             state_ptr as i32,
         );
 
         ic0::call_data_append(args_raw.as_ptr() as i32, args_raw.len() as i32);
         payment_func();
-        ic0::call_on_cleanup(cleanup as usize as i32, state_ptr as i32);
+        ic0::call_on_cleanup(reply_cleanup as usize as i32, state_ptr as i32);
         ic0::call_perform()
     };
 
     // 0 is a special error code meaning call_simple call succeeded.
     if err_code != 0 {
+        // The callbacks won't be called, so the reference passed to them
+        // has to be reclaimed here.
+        drop(unsafe { WasmCell::from_raw(state_ptr) });
+        let error = CallPerformError::from(err_code);
         let mut state = state.borrow_mut();
-        state.result = Some(Err((
-            RejectionCode::from(err_code),
-            "Couldn't send message".to_string(),
-        )));
+        state.result = Some(Err((error.rejection_code(), error.to_string())));
+    } else {
+        OUTSTANDING_CALLS.with(|n| n.set(n.get() + 1));
     }
     CallFuture { state }
 }
 
-fn decoder_error_to_reject<T>(err: candid::error::Error) -> (RejectionCode, String) {
+fn decoder_error_to_reject<T>(
+    id: &Principal,
+    method: &str,
+    err: candid::error::Error,
+) -> (RejectionCode, String) {
     (
         RejectionCode::CanisterError,
         format!(
-            "failed to decode canister response as {}: {}",
+            "failed to decode reply from {}.{} as {}: {}",
+            id,
+            method,
             std::any::type_name::<T>(),
             err
         ),
@@ -406,8 +738,9 @@ fn decoder_error_to_reject<T>(err: candid::error::Error) -> (RejectionCode, Stri
 
 /// Performs an asynchronous call to another canister using the [System API](https://internetcomputer.org/docs/current/references/ic-interface-spec/#system-api-call).
 ///
-/// If the reply payload is not a valid encoding of the expected type `T`,
-/// the call results in [RejectionCode::CanisterError] error.
+/// If the reply payload is not a valid encoding of the expected type `R`,
+/// the call results in [RejectionCode::CanisterError] error. The error
+/// message mentions the callee and the method to ease debugging.
 pub fn call<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
     id: Principal,
     method: &str,
@@ -415,9 +748,10 @@ pub fn call<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
 ) -> impl Future<Output = CallResult<R>> {
     let args_raw = encode_args(args).expect("Failed to encode arguments.");
     let fut = call_raw(id, method, &args_raw, 0);
-    async {
+    let method = method.to_string();
+    async move {
         let bytes = fut.await?;
-        decode_args(&bytes).map_err(decoder_error_to_reject::<T>)
+        decode_args(&bytes).map_err(|err| decoder_error_to_reject::<R>(&id, &method, err))
     }
 }
 
@@ -430,9 +764,10 @@ pub fn call_with_payment<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
 ) -> impl Future<Output = CallResult<R>> {
     let args_raw = encode_args(args).expect("Failed to encode arguments.");
     let fut = call_raw(id, method, &args_raw, cycles);
-    async {
+    let method = method.to_string();
+    async move {
         let bytes = fut.await?;
-        decode_args(&bytes).map_err(decoder_error_to_reject::<T>)
+        decode_args(&bytes).map_err(|err| decoder_error_to_reject::<R>(&id, &method, err))
     }
 }
 
@@ -445,158 +780,990 @@ pub fn call_with_payment128<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
 ) -> impl Future<Output = CallResult<R>> {
     let args_raw = encode_args(args).expect("Failed to encode arguments.");
     let fut = call_raw128(id, method, &args_raw, cycles);
-    async {
+    let method = method.to_string();
+    async move {
         let bytes = fut.await?;
-        decode_args(&bytes).map_err(decoder_error_to_reject::<T>)
+        decode_args(&bytes).map_err(|err| decoder_error_to_reject::<R>(&id, &method, err))
     }
 }
 
-/// Returns a result that maps over the call
+/// Like [call], but decodes a reply made of a single value.
 ///
-/// It will be Ok(T) if the call succeeded (with T being the arg_data),
-/// and [reject_message()] if it failed.
-pub fn result<T: for<'a> ArgumentDecoder<'a>>() -> Result<T, String> {
-    match reject_code() {
-        RejectionCode::NoError => {
-            decode_args(&arg_data_raw()).map_err(|e| format!("Failed to decode arguments: {}", e))
-        }
-        _ => Err(reject_message()),
+/// This saves destructuring a one-element tuple:
+///
+/// ```rust,no_run
+/// # use ic_cdk::api::call::{call_one, CallResult};
+/// # use candid::Principal;
+/// async fn balance(ledger: Principal) -> CallResult<u64> {
+///     call_one(ledger, "balance", ()).await
+/// }
+/// ```
+pub fn call_one<T, R>(id: Principal, method: &str, args: T) -> impl Future<Output = CallResult<R>>
+where
+    T: ArgumentEncoder,
+    R: CandidType + for<'a> Deserialize<'a>,
+{
+    let fut = call::<T, (R,)>(id, method, args);
+    async move { fut.await.map(|(r,)| r) }
+}
+
+/// Like [call], but doesn't trap if the arguments can't be encoded.
+///
+/// All the failure modes are reported through [CallError]: encoding the
+/// arguments, the call being rejected, and decoding the reply. A call whose
+/// deadline expired is reported as [CallError::TimedOut] rather than as a
+/// rejection, as the callee may still have executed it.
+pub fn try_call<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+    id: Principal,
+    method: &str,
+    args: T,
+) -> impl Future<Output = Result<R, CallError>> {
+    let fut = encode_args(args).map(|args_raw| call_raw(id, method, &args_raw, 0));
+    let method = method.to_string();
+    async move {
+        let bytes = fut
+            .map_err(|err| CallError::Encode(err.to_string()))?
+            .await
+            .map_err(CallError::from)?;
+        decode_args(&bytes)
+            .map_err(|err| CallError::Decode(decoder_error_to_reject::<R>(&id, &method, err).1))
     }
 }
 
-/// Returns the rejection code for the call.
-pub fn reject_code() -> RejectionCode {
-    let code = unsafe { ic0::msg_reject_code() };
-    RejectionCode::from(code)
+/// How [fan_out] treats the calls which fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FanOutPolicy {
+    /// Fail if any call fails.
+    AllOrNothing,
+    /// Never fail, collecting the calls which succeed.
+    BestEffort,
+    /// Fail unless at least this many calls succeed.
+    Quorum(usize),
 }
 
-/// Returns the rejection message.
-pub fn reject_message() -> String {
-    let len: u32 = unsafe { ic0::msg_reject_msg_size() as u32 };
-    let mut bytes = vec![0u8; len as usize];
-    unsafe {
-        ic0::msg_reject_msg_copy(bytes.as_mut_ptr() as i32, 0, len as i32);
+impl FanOutPolicy {
+    /// Splits the results of the calls into successes and failures, and
+    /// checks them against the policy.
+    ///
+    /// Returns `Err` with all the results if the policy is not met.
+    pub fn aggregate<R>(
+        &self,
+        results: impl IntoIterator<Item = (Principal, CallResult<R>)>,
+    ) -> Result<FanOutResult<R>, FanOutResult<R>> {
+        let mut aggregated = FanOutResult {
+            successes: Vec::new(),
+            failures: Vec::new(),
+        };
+        for (id, result) in results {
+            match result {
+                Ok(r) => aggregated.successes.push((id, r)),
+                Err(e) => aggregated.failures.push((id, e)),
+            }
+        }
+        let met = match *self {
+            Self::AllOrNothing => aggregated.failures.is_empty(),
+            Self::BestEffort => true,
+            Self::Quorum(n) => aggregated.successes.len() >= n,
+        };
+        if met {
+            Ok(aggregated)
+        } else {
+            Err(aggregated)
+        }
     }
-    String::from_utf8_lossy(&bytes).to_string()
 }
 
-/// Rejects the current call with the message.
-pub fn reject(message: &str) {
-    let err_message = message.as_bytes();
-    unsafe {
-        ic0::msg_reject(err_message.as_ptr() as i32, err_message.len() as i32);
+/// The results of the calls made by [fan_out].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FanOutResult<R> {
+    /// The replies of the calls which succeeded, in the order of the targets.
+    pub successes: Vec<(Principal, R)>,
+    /// The errors of the calls which failed, in the order of the targets.
+    pub failures: Vec<(Principal, (RejectionCode, String))>,
+}
+
+/// Calls `method` with the same arguments on every target and aggregates
+/// the replies according to `policy`.
+///
+/// All the calls are sent before any reply is awaited, so they are processed
+/// concurrently. A reply which can't be decoded counts as a failed call.
+///
+/// Returns `Err` with all the successes and failures if the policy is not met.
+pub fn fan_out<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+    targets: &[Principal],
+    method: &str,
+    args: T,
+    policy: FanOutPolicy,
+) -> impl Future<Output = Result<FanOutResult<R>, FanOutResult<R>>> {
+    let args_raw = encode_args(args).expect("Failed to encode arguments.");
+    let calls: Vec<_> = targets
+        .iter()
+        .map(|id| (*id, call_raw(*id, method, &args_raw, 0)))
+        .collect();
+    let method = method.to_string();
+    async move {
+        let mut results = Vec::with_capacity(calls.len());
+        for (id, fut) in calls {
+            let result = fut.await.and_then(|bytes| {
+                decode_args(&bytes).map_err(|err| decoder_error_to_reject::<R>(&id, &method, err))
+            });
+            results.push((id, result));
+        }
+        policy.aggregate(results)
     }
 }
 
-/// An io::Writer for message replies.
-pub struct CallReplyWriter;
+/// A method of another canister with fixed argument and result types.
+///
+/// Calling a method with arguments of the wrong types is only detected by
+/// the callee, which rejects the call. A `TypedCall` fixes the types once,
+/// typically from the Candid interface of the callee, so that mismatches
+/// are caught by the compiler at every call site.
+///
+/// ```rust,no_run
+/// # use ic_cdk::api::call::TypedCall;
+/// # use candid::Principal;
+/// # async fn example(ledger: Principal) {
+/// let balance = TypedCall::<(String,), (u64,)>::new(ledger, "balance");
+/// // `balance.call((42,))` doesn't compile.
+/// let (tokens,) = balance.call(("alice".to_string(),)).await.unwrap();
+/// # }
+/// ```
+///
+/// `A` and `R` are tuples of the argument and result types.
+pub struct TypedCall<A, R> {
+    id: Principal,
+    method: String,
+    _marker: PhantomData<fn(A) -> R>,
+}
 
-impl std::io::Write for CallReplyWriter {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        unsafe {
-            ic0::msg_reply_data_append(buf.as_ptr() as i32, buf.len() as i32);
+impl<A: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>> TypedCall<A, R> {
+    /// Creates a typed call to `method` of the canister `id`.
+    pub fn new(id: Principal, method: &str) -> Self {
+        Self {
+            id,
+            method: method.to_string(),
+            _marker: PhantomData,
         }
-        Ok(buf.len())
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+    /// Returns the id of the callee.
+    pub fn id(&self) -> Principal {
+        self.id
     }
-}
 
-/// Replies to the current call with a candid argument.
-pub fn reply<T: ArgumentEncoder>(reply: T) {
-    write_args(&mut CallReplyWriter, reply).expect("Could not encode reply.");
-    unsafe {
-        ic0::msg_reply();
+    /// Returns the name of the method.
+    pub fn method(&self) -> &str {
+        &self.method
     }
-}
 
-/// Returns the amount of cycles that were transferred by the caller
-/// of the current call, and is still available in this message.
-pub fn msg_cycles_available() -> u64 {
-    unsafe { ic0::msg_cycles_available() as u64 }
-}
+    /// Performs the call, see [call].
+    pub fn call(&self, args: A) -> impl Future<Output = CallResult<R>> {
+        call(self.id, &self.method, args)
+    }
 
-/// Returns the amount of cycles that were transferred by the caller
-/// of the current call, and is still available in this message.
-pub fn msg_cycles_available128() -> u128 {
-    let mut recv = 0u128;
-    unsafe {
-        ic0::msg_cycles_available128(&mut recv as *mut u128 as i32);
+    /// Performs the call with `cycles` attached to it, see [call_with_payment128].
+    pub fn call_with_payment128(
+        &self,
+        args: A,
+        cycles: u128,
+    ) -> impl Future<Output = CallResult<R>> {
+        call_with_payment128(self.id, &self.method, args, cycles)
     }
-    recv
 }
 
-/// Returns the amount of cycles that came back with the response as a refund.
-///
-/// The refund has already been added to the canister balance automatically.
-pub fn msg_cycles_refunded() -> u64 {
-    unsafe { ic0::msg_cycles_refunded() as u64 }
+/// The state of the circuit of a callee in a [CircuitBreaker].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through.
+    Closed,
+    /// Calls fail right away until the cool-down expires, and while the
+    /// probe sent once it expired is in flight.
+    Open,
+    /// The cool-down expired: the next call probes whether the callee
+    /// recovered, closing the circuit if it succeeds and opening it again if
+    /// it fails.
+    HalfOpen,
 }
 
-/// Returns the amount of cycles that came back with the response as a refund.
-///
-/// The refund has already been added to the canister balance automatically.
-pub fn msg_cycles_refunded128() -> u128 {
-    let mut recv = 0u128;
-    unsafe {
-        ic0::msg_cycles_refunded128(&mut recv as *mut u128 as i32);
-    }
-    recv
+#[derive(Clone, Copy, Debug, Default)]
+struct CalleeHealth {
+    consecutive_failures: u32,
+    open_until: Option<Timestamp>,
+    probe_in_flight: bool,
 }
 
-/// Moves cycles from the call to the canister balance.
+/// Stops calling the canisters which keep failing, see [call_with_breaker].
 ///
-/// The actual amount moved will be returned.
-pub fn msg_cycles_accept(max_amount: u64) -> u64 {
-    // TODO: should we assert the u64 input is within the range of i64?
-    unsafe { ic0::msg_cycles_accept(max_amount as i64) as u64 }
-}
-
-/// Moves cycles from the call to the canister balance.
+/// After `failure_threshold` consecutive failed calls to a callee, its
+/// circuit opens: calls to it fail right away with
+/// [RejectionCode::SysTransient] and a message starting with "circuit
+/// breaker open", without being sent, which saves the cycles and the
+/// latency of calls that are bound to fail. Once the cool-down
+/// expires, the circuit is half-open and the next call is sent to probe the
+/// callee. The other calls fail right away until the outcome of the probe
+/// is recorded.
 ///
-/// The actual amount moved will be returned.
-pub fn msg_cycles_accept128(max_amount: u128) -> u128 {
-    let high = (max_amount >> 64) as u64;
-    let low = (max_amount & u64::MAX as u128) as u64;
-    let mut recv = 0u128;
-    unsafe {
-        ic0::msg_cycles_accept128(high as i64, low as i64, &mut recv as *mut u128 as i32);
-    }
-    recv
+/// Only system errors and callee traps count as failures: a call which
+/// the callee rejected explicitly ([RejectionCode::CanisterReject]) shows
+/// that the callee is up. [call_with_breaker] doesn't record the calls
+/// which were never sent, e.g. because of the limit set with
+/// [set_max_outstanding_calls], nor the replies which fail to decode.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cool_down: Duration,
+    callees: HashMap<Principal, CalleeHealth>,
 }
 
-/// Returns the argument data as bytes.
-pub fn arg_data_raw() -> Vec<u8> {
-    unsafe {
-        let len: usize = ic0::msg_arg_data_size() as usize;
-        let mut bytes = Vec::with_capacity(len);
-        ic0::msg_arg_data_copy(bytes.as_mut_ptr() as i32, 0, len as i32);
-        bytes.set_len(len);
-        bytes
+impl CircuitBreaker {
+    /// Creates a breaker opening the circuit of a callee for `cool_down`
+    /// after `failure_threshold` consecutive failures.
+    pub fn new(failure_threshold: u32, cool_down: Duration) -> Self {
+        assert!(
+            failure_threshold > 0,
+            "the failure threshold of a circuit breaker must be positive"
+        );
+        Self {
+            failure_threshold,
+            cool_down,
+            callees: HashMap::new(),
+        }
     }
-}
-
-/// Get the len of the raw-argument-data-bytes.
-pub fn arg_data_raw_size() -> usize {
-    unsafe { ic0::msg_arg_data_size() as usize }
-}
 
-/// Replies with the bytes passed
-pub fn reply_raw(buf: &[u8]) {
-    unsafe {
-        if !buf.is_empty() {
-            ic0::msg_reply_data_append(buf.as_ptr() as i32, buf.len() as i32)
+    /// Returns the state of the circuit of `id` at the given time.
+    pub fn state_at(&self, id: &Principal, now: Timestamp) -> CircuitState {
+        let health = match self.callees.get(id) {
+            Some(health) => health,
+            None => return CircuitState::Closed,
         };
-        ic0::msg_reply();
+        match health.open_until {
+            None => CircuitState::Closed,
+            Some(open_until) if now < open_until || health.probe_in_flight => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
     }
-}
 
-/// Returns the argument data in the current call. Traps if the data cannot be
-/// decoded.
-pub fn arg_data<R: for<'a> ArgumentDecoder<'a>>() -> R {
-    let bytes = arg_data_raw();
+    /// Returns the state of the circuit of `id`.
+    pub fn state(&self, id: &Principal) -> CircuitState {
+        self.state_at(id, Timestamp::now())
+    }
+
+    // Decides whether a call to `id` is sent at the given time. Returns
+    // whether the call is the probe of a half-open circuit, which keeps the
+    // circuit open for the other calls until its outcome is recorded, or
+    // `None` if the call must fail right away.
+    fn admit_at(&mut self, id: &Principal, now: Timestamp) -> Option<bool> {
+        match self.state_at(id, now) {
+            CircuitState::Closed => Some(false),
+            CircuitState::Open => None,
+            CircuitState::HalfOpen => {
+                if let Some(health) = self.callees.get_mut(id) {
+                    health.probe_in_flight = true;
+                }
+                Some(true)
+            }
+        }
+    }
+
+    // Forgets the probe to `id` whose outcome won't be recorded, so that the
+    // next call probes the callee instead.
+    fn abandon_probe(&mut self, id: &Principal) {
+        if let Some(health) = self.callees.get_mut(id) {
+            health.probe_in_flight = false;
+        }
+    }
+
+    /// Records the outcome of a call to `id` made at the given time.
+    pub fn record_at<R>(&mut self, id: Principal, result: &CallResult<R>, now: Timestamp) {
+        let failed = match result {
+            Ok(_) | Err((RejectionCode::CanisterReject, _)) => false,
+            Err(_) => true,
+        };
+        if !failed {
+            self.callees.remove(&id);
+            return;
+        }
+        let health = self.callees.entry(id).or_default();
+        health.probe_in_flight = false;
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+        if health.consecutive_failures >= self.failure_threshold {
+            // A cool-down of `Duration::MAX` keeps the circuit open for good.
+            let open_until = now
+                .checked_add(self.cool_down)
+                .unwrap_or(Timestamp::from_nanos(u64::MAX));
+            health.open_until = Some(open_until);
+        }
+    }
+
+    /// Records the outcome of a call to `id`.
+    pub fn record<R>(&mut self, id: Principal, result: &CallResult<R>) {
+        self.record_at(id, result, Timestamp::now())
+    }
+}
+
+/// Like [call], but fails right away if the circuit of `id` is open in
+/// `breaker`, and records the outcome of the call in `breaker`.
+///
+/// The breaker is kept in a thread local so that no borrow of it is held
+/// while the call is awaited, and other messages can use it meanwhile:
+///
+/// ```rust,no_run
+/// # use ic_cdk::api::call::{call_with_breaker, CallResult, CircuitBreaker};
+/// # use candid::Principal;
+/// # use std::cell::RefCell;
+/// # use std::time::Duration;
+/// thread_local! {
+///     static BREAKER: RefCell<CircuitBreaker> =
+///         RefCell::new(CircuitBreaker::new(3, Duration::from_secs(60)));
+/// }
+///
+/// async fn balance(ledger: Principal) -> CallResult<(u64,)> {
+///     call_with_breaker(ledger, "balance", (), &BREAKER).await
+/// }
+/// ```
+pub async fn call_with_breaker<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+    id: Principal,
+    method: &str,
+    args: T,
+    breaker: &'static LocalKey<RefCell<CircuitBreaker>>,
+) -> CallResult<R> {
+    let is_probe = match breaker.with(|b| b.borrow_mut().admit_at(&id, Timestamp::now())) {
+        Some(is_probe) => is_probe,
+        None => {
+            return Err((
+                RejectionCode::SysTransient,
+                format!("circuit breaker open for {}", id),
+            ))
+        }
+    };
+    let abandon_probe = move || breaker.with(|b| b.borrow_mut().abandon_probe(&id));
+    // The task may be dropped before the outcome of the probe is recorded,
+    // e.g. by `ic_select` or by a cleanup callback.
+    let probe_guard = is_probe.then(|| crate::defer(abandon_probe));
+    let args_raw = encode_args(args).expect("Failed to encode arguments.");
+    let future = call_raw_internal(id, method, &args_raw, || {});
+    // A call which wasn't sent says nothing about the health of the callee.
+    let sent = future.is_sent();
+    let result = future.await;
+    if sent {
+        if let Some(probe_guard) = probe_guard {
+            probe_guard.cancel();
+        }
+        breaker.with(|b| b.borrow_mut().record(id, &result));
+    }
+    decode_args(&result?).map_err(|err| decoder_error_to_reject::<R>(&id, method, err))
+}
+
+/// A cache of the replies of calls, see [cached_call].
+///
+/// Replies are keyed by a 64-bit hash of the callee, the method and the
+/// encoded arguments, and expire `ttl` after they were received. Only the
+/// calls which succeeded are cached. Once the cache holds `max_entries`
+/// replies, caching another one evicts the oldest.
+#[derive(Clone, Debug)]
+pub struct CallCache {
+    ttl: Duration,
+    max_entries: usize,
+    hasher: RandomState,
+    // The replies with their expiry and the number of their insertion.
+    entries: HashMap<u64, (Vec<u8>, Timestamp, u64)>,
+    // The insertions, from the oldest to the newest. Those of the replies
+    // which were replaced since are skipped when evicting.
+    order: VecDeque<(u64, Timestamp, u64)>,
+    insertions: u64,
+}
+
+impl CallCache {
+    /// Creates an empty cache keeping at most `max_entries` replies for
+    /// `ttl`.
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            hasher: RandomState::new(),
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            insertions: 0,
+        }
+    }
+
+    fn key(&self, id: Principal, method: &str, args_raw: &[u8]) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        (id, method, args_raw).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns the reply of a call to `method` of `id` with `args_raw`, if
+    /// it is cached and not expired at the given time.
+    pub fn get_at(
+        &self,
+        id: Principal,
+        method: &str,
+        args_raw: &[u8],
+        now: Timestamp,
+    ) -> Option<&[u8]> {
+        self.entries
+            .get(&self.key(id, method, args_raw))
+            .filter(|(_, expiry, _)| now < *expiry)
+            .map(|(reply, _, _)| reply.as_slice())
+    }
+
+    /// Caches the reply of a call to `method` of `id` with `args_raw`,
+    /// received at the given time.
+    ///
+    /// The expired replies are evicted at the same time, and so is the
+    /// oldest reply if the cache is full.
+    pub fn insert_at(
+        &mut self,
+        id: Principal,
+        method: &str,
+        args_raw: &[u8],
+        reply: Vec<u8>,
+        now: Timestamp,
+    ) {
+        if self.max_entries == 0 {
+            return;
+        }
+        while matches!(self.order.front(), Some((_, expiry, _)) if *expiry <= now) {
+            self.evict_oldest();
+        }
+        let key = self.key(id, method, args_raw);
+        while !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            self.evict_oldest();
+        }
+        // A ttl of `Duration::MAX` keeps the reply for good.
+        let expiry = now
+            .checked_add(self.ttl)
+            .unwrap_or(Timestamp::from_nanos(u64::MAX));
+        self.insertions += 1;
+        self.entries.insert(key, (reply, expiry, self.insertions));
+        self.order.push_back((key, expiry, self.insertions));
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((key, _, insertion)) = self.order.pop_front() {
+            if matches!(self.entries.get(&key), Some((_, _, i)) if *i == insertion) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    /// Returns the number of cached replies, including the expired ones
+    /// which weren't evicted yet.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no reply is cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the cached replies.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// Like [call], but returns the reply cached in `cache` if there is a
+/// fresh one, and caches the reply otherwise.
+///
+/// Only use it for idempotent reads: a cached reply is returned without
+/// calling the callee at all, so any side effect of the method is skipped.
+/// The cache is kept in a thread local so that no borrow of it is held
+/// while the call is awaited:
+///
+/// ```rust,no_run
+/// # use ic_cdk::api::call::{cached_call, CallCache, CallResult};
+/// # use candid::Principal;
+/// # use std::cell::RefCell;
+/// # use std::time::Duration;
+/// thread_local! {
+///     static CACHE: RefCell<CallCache> =
+///         RefCell::new(CallCache::new(Duration::from_secs(60), 1_000));
+/// }
+///
+/// async fn symbol(ledger: Principal) -> CallResult<(String,)> {
+///     cached_call(&CACHE, ledger, "symbol", ()).await
+/// }
+/// ```
+pub fn cached_call<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+    cache: &'static LocalKey<RefCell<CallCache>>,
+    id: Principal,
+    method: &str,
+    args: T,
+) -> impl Future<Output = CallResult<R>> {
+    let args_raw = encode_args(args).expect("Failed to encode arguments.");
+    let cached = cache.with(|c| {
+        c.borrow()
+            .get_at(id, method, &args_raw, Timestamp::now())
+            .map(|reply| reply.to_vec())
+    });
+    // The call is only sent on a cache miss.
+    let lookup = cached.ok_or_else(|| call_raw(id, method, &args_raw, 0));
+    let method = method.to_string();
+    async move {
+        let bytes = match lookup {
+            Ok(bytes) => bytes,
+            Err(fut) => {
+                let bytes = fut.await?;
+                cache.with(|c| {
+                    c.borrow_mut().insert_at(
+                        id,
+                        &method,
+                        &args_raw,
+                        bytes.clone(),
+                        Timestamp::now(),
+                    )
+                });
+                bytes
+            }
+        };
+        decode_args(&bytes).map_err(|err| decoder_error_to_reject::<R>(&id, &method, err))
+    }
+}
+
+/// A token identifying a logical operation across retried calls.
+///
+/// The caller generates one token per logical operation (e.g. from
+/// [raw_rand](crate::api::management_canister::main::raw_rand)) and sends
+/// it with every attempt using [call_idempotent]. The callee reads it with
+/// [idempotent_arg_data] and skips operations whose token it has already
+/// seen, so retries don't execute the side effects twice.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IdempotencyToken(pub Vec<u8>);
+
+/// The number of attempts made by [call_idempotent].
+pub const IDEMPOTENT_CALL_ATTEMPTS: usize = 3;
+
+// The envelope of an idempotent call is the token followed by the arguments.
+fn encode_idempotent_args<T: ArgumentEncoder>(
+    token: &IdempotencyToken,
+    args: T,
+) -> Result<Vec<u8>, candid::Error> {
+    let mut builder = candid::ser::IDLBuilder::new();
+    builder.arg(token)?;
+    args.encode(&mut builder)?;
+    builder.serialize_to_vec()
+}
+
+fn decode_idempotent_args<R: for<'a> ArgumentDecoder<'a>>(
+    bytes: &[u8],
+) -> Result<(IdempotencyToken, R), candid::Error> {
+    let mut de = candid::de::IDLDeserialize::new(bytes)?;
+    let token = de.get_value::<IdempotencyToken>()?;
+    let args = R::decode(&mut de)?;
+    de.done()?;
+    Ok((token, args))
+}
+
+// Whether [call_idempotent] makes another attempt after the given one. A
+// call refused because the limit of outstanding calls is reached isn't
+// retried, as the retry would be refused right away as well.
+fn retries_idempotent_call<R>(result: &CallResult<R>, attempt: usize) -> bool {
+    matches!(result, Err((RejectionCode::SysTransient, _)))
+        && attempt < IDEMPOTENT_CALL_ATTEMPTS
+        && outstanding_call_limit_error().is_none()
+}
+
+/// Performs an asynchronous call which is safe to retry, as the callee can
+/// deduplicate it using the `token`.
+///
+/// The call is sent with the `token` prepended to `args`, and is retried up
+/// to [IDEMPOTENT_CALL_ATTEMPTS] times while it is rejected with
+/// [RejectionCode::SysTransient], unless the limit set with
+/// [set_max_outstanding_calls] is reached. The callee must read its
+/// arguments with [idempotent_arg_data].
+pub fn call_idempotent<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+    id: Principal,
+    method: &str,
+    args: T,
+    token: IdempotencyToken,
+) -> impl Future<Output = CallResult<R>> {
+    let args_raw = encode_idempotent_args(&token, args).expect("Failed to encode arguments.");
+    let method = method.to_string();
+    async move {
+        let mut attempt = 1;
+        let bytes = loop {
+            let result = call_raw(id, &method, &args_raw, 0).await;
+            if !retries_idempotent_call(&result, attempt) {
+                break result?;
+            }
+            attempt += 1;
+        };
+        decode_args(&bytes).map_err(|err| decoder_error_to_reject::<R>(&id, &method, err))
+    }
+}
+
+// The magic number at the start of every gzip stream. It can't be confused
+// with a Candid message, which starts with "DIDL".
+#[cfg(feature = "gzip")]
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+#[cfg(feature = "gzip")]
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(bytes)
+        .and_then(|()| encoder.finish())
+        .expect("Failed to compress.")
+}
+
+/// The maximum size of a reply once decompressed by [call_decompress].
+///
+/// The callee controls the compressed bytes, so a small reply could
+/// otherwise inflate until the caller runs out of memory. Replies which
+/// decompress to more bytes fail to decode.
+#[cfg(feature = "gzip")]
+pub const MAX_DECOMPRESSED_REPLY_SIZE: usize = 32 * 1024 * 1024;
+
+#[cfg(feature = "gzip")]
+fn decode_maybe_compressed<R: for<'a> ArgumentDecoder<'a>>(
+    bytes: &[u8],
+    limit: usize,
+) -> Result<R, candid::Error> {
+    use std::io::Read;
+    if !bytes.starts_with(GZIP_MAGIC) {
+        return decode_args(bytes);
+    }
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(bytes)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| candid::Error::msg(format!("failed to decompress: {}", e)))?;
+    if decompressed.len() > limit {
+        return Err(candid::Error::msg(format!(
+            "failed to decompress: the payload exceeds {} bytes",
+            limit
+        )));
+    }
+    decode_args(&decompressed)
+}
+
+/// Performs an asynchronous call to a method which may reply with a
+/// gzip-compressed Candid payload, see [reply_compressed].
+///
+/// Replies starting with the gzip magic number are decompressed before
+/// being decoded, other replies are decoded as is. Replies decompressing to
+/// more than [MAX_DECOMPRESSED_REPLY_SIZE] bytes fail to decode.
+///
+/// Requires the `gzip` feature.
+#[cfg(feature = "gzip")]
+pub fn call_decompress<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+    id: Principal,
+    method: &str,
+    args: T,
+) -> impl Future<Output = CallResult<R>> {
+    let args_raw = encode_args(args).expect("Failed to encode arguments.");
+    let fut = call_raw(id, method, &args_raw, 0);
+    let method = method.to_string();
+    async move {
+        let bytes = fut.await?;
+        decode_maybe_compressed(&bytes, MAX_DECOMPRESSED_REPLY_SIZE)
+            .map_err(|err| decoder_error_to_reject::<R>(&id, &method, err))
+    }
+}
+
+/// Returns a result that maps over the call
+///
+/// It will be Ok(T) if the call succeeded (with T being the arg_data),
+/// and [reject_message()] if it failed.
+pub fn result<T: for<'a> ArgumentDecoder<'a>>() -> Result<T, String> {
+    match reject_code() {
+        RejectionCode::NoError => {
+            decode_args(&arg_data_raw()).map_err(|e| format!("Failed to decode arguments: {}", e))
+        }
+        _ => Err(reject_message()),
+    }
+}
+
+/// Returns the rejection code for the call.
+pub fn reject_code() -> RejectionCode {
+    let code = unsafe { ic0::msg_reject_code() };
+    RejectionCode::from(code)
+}
+
+/// Returns the rejection message.
+pub fn reject_message() -> String {
+    let len: u32 = unsafe { ic0::msg_reject_msg_size() as u32 };
+    let mut bytes = vec![0u8; len as usize];
+    unsafe {
+        ic0::msg_reject_msg_copy(bytes.as_mut_ptr() as i32, 0, len as i32);
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Returns the rejection message, truncated to at most `max` bytes.
+///
+/// Unlike [reject_message], it never allocates more than `max` bytes, so it
+/// is safe to use when handling rejects from untrusted callees. The
+/// truncation may split a multi-byte character, which is then replaced
+/// with `U+FFFD`.
+pub fn reject_message_truncated(max: usize) -> String {
+    let len = unsafe { ic0::msg_reject_msg_size() as usize }.min(max);
+    let mut bytes = vec![0u8; len];
+    unsafe {
+        ic0::msg_reject_msg_copy(bytes.as_mut_ptr() as i32, 0, len as i32);
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Rejects the current call with the message.
+pub fn reject(message: &str) {
+    let err_message = message.as_bytes();
+    unsafe {
+        ic0::msg_reject(err_message.as_ptr() as i32, err_message.len() as i32);
+    }
+}
+
+/// An io::Writer for message replies.
+pub struct CallReplyWriter;
+
+impl std::io::Write for CallReplyWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        unsafe {
+            ic0::msg_reply_data_append(buf.as_ptr() as i32, buf.len() as i32);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An io::Writer assembling the reply of the current call from chunks.
+///
+/// Every write appends the bytes to the reply buffer of the current call
+/// directly, like [CallReplyWriter], so a large reply can be assembled from
+/// chunks without building a single `Vec<u8>` first. Call
+/// [ChunkedReplyWriter::finish] once all the chunks are written to actually
+/// send the reply.
+///
+/// ```no_run
+/// use ic_cdk::api::call::ChunkedReplyWriter;
+/// use std::io::Write;
+///
+/// #[export_name = "canister_query get_file"]
+/// fn get_file() {
+///     let mut writer = ChunkedReplyWriter::new();
+///     for chunk in [b"first chunk", b"other chunk"] {
+///         writer.write_all(chunk).unwrap();
+///     }
+///     writer.finish();
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct ChunkedReplyWriter {
+    bytes_written: usize,
+}
+
+impl ChunkedReplyWriter {
+    /// Creates a writer appending to the reply of the current call.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of bytes appended to the reply so far.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// Replies to the current call with the bytes written so far.
+    pub fn finish(self) {
+        unsafe {
+            ic0::msg_reply();
+        }
+    }
+}
+
+impl std::io::Write for ChunkedReplyWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = std::io::Write::write(&mut CallReplyWriter, buf)?;
+        self.bytes_written += len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Replies to the current call with a candid argument.
+///
+/// [candid::Reserved] values are encoded as `reserved`, which decodes on
+/// the caller side as any type expecting `reserved`, `opt` or `null`.
+/// A reply of type [candid::Empty] can't be constructed.
+pub fn reply<T: ArgumentEncoder>(reply: T) {
+    write_args(&mut CallReplyWriter, reply).expect("Could not encode reply.");
+    unsafe {
+        ic0::msg_reply();
+    }
+}
+
+/// Replies to the current call with a gzip-compressed candid argument.
+///
+/// The reply is the gzip stream of the Candid encoding of `reply`, which
+/// callers must decompress before decoding, e.g. with [call_decompress].
+/// Compression trades instructions (and so cycles) for a smaller reply:
+/// it pays off for large, redundant payloads returned over query calls, but
+/// not for small or already compressed ones.
+///
+/// Requires the `gzip` feature.
+#[cfg(feature = "gzip")]
+pub fn reply_compressed<T: ArgumentEncoder>(reply: T) {
+    let bytes = encode_args(reply).expect("Could not encode reply.");
+    reply_raw(&gzip(&bytes));
+}
+
+/// Returns the amount of cycles that were transferred by the caller
+/// of the current call, and is still available in this message.
+pub fn msg_cycles_available() -> u64 {
+    unsafe { ic0::msg_cycles_available() as u64 }
+}
+
+/// Returns the amount of cycles that were transferred by the caller
+/// of the current call, and is still available in this message.
+pub fn msg_cycles_available128() -> u128 {
+    let mut recv = 0u128;
+    unsafe {
+        ic0::msg_cycles_available128(&mut recv as *mut u128 as i32);
+    }
+    recv
+}
+
+/// Returns the amount of cycles that came back with the response as a refund.
+///
+/// The refund has already been added to the canister balance automatically.
+pub fn msg_cycles_refunded() -> u64 {
+    unsafe { ic0::msg_cycles_refunded() as u64 }
+}
+
+/// Returns the amount of cycles that came back with the response as a refund.
+///
+/// The refund has already been added to the canister balance automatically.
+pub fn msg_cycles_refunded128() -> u128 {
+    let mut recv = 0u128;
+    unsafe {
+        ic0::msg_cycles_refunded128(&mut recv as *mut u128 as i32);
+    }
+    recv
+}
+
+/// Moves cycles from the call to the canister balance.
+///
+/// The actual amount moved will be returned.
+pub fn msg_cycles_accept(max_amount: u64) -> u64 {
+    // TODO: should we assert the u64 input is within the range of i64?
+    unsafe { ic0::msg_cycles_accept(max_amount as i64) as u64 }
+}
+
+/// Moves cycles from the call to the canister balance.
+///
+/// The actual amount moved will be returned.
+pub fn msg_cycles_accept128(max_amount: u128) -> u128 {
+    let high = (max_amount >> 64) as u64;
+    let low = (max_amount & u64::MAX as u128) as u64;
+    let mut recv = 0u128;
+    unsafe {
+        ic0::msg_cycles_accept128(high as i64, low as i64, &mut recv as *mut u128 as i32);
+    }
+    recv
+}
+
+/// Returns the argument data as bytes.
+pub fn arg_data_raw() -> Vec<u8> {
+    unsafe {
+        let len: usize = ic0::msg_arg_data_size() as usize;
+        let mut bytes = Vec::with_capacity(len);
+        ic0::msg_arg_data_copy(bytes.as_mut_ptr() as i32, 0, len as i32);
+        bytes.set_len(len);
+        bytes
+    }
+}
+
+/// Get the len of the raw-argument-data-bytes.
+pub fn arg_data_raw_size() -> usize {
+    unsafe { ic0::msg_arg_data_size() as usize }
+}
+
+/// A reader over the argument data of the current message.
+///
+/// Every read copies the next bytes of the argument data (or of the reply,
+/// in a reply callback) directly from the message buffer, so the data can
+/// be fed to a streaming deserializer without building a single `Vec<u8>`
+/// first. This is the reading counterpart of [ChunkedReplyWriter].
+#[derive(Debug, Default)]
+pub struct ArgDataReader {
+    offset: usize,
+}
+
+impl ArgDataReader {
+    /// Creates a reader starting at the beginning of the argument data.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn bytes_read(&self) -> usize {
+        self.offset
+    }
+
+    // Reads the next bytes of an argument of `size` bytes into `buf`, with
+    // `copy` copying the bytes at the given offset of the argument.
+    fn read_with(
+        &mut self,
+        buf: &mut [u8],
+        size: usize,
+        copy: impl FnOnce(&mut [u8], usize),
+    ) -> usize {
+        let len = buf.len().min(size.saturating_sub(self.offset));
+        if len > 0 {
+            copy(&mut buf[..len], self.offset);
+            self.offset += len;
+        }
+        len
+    }
+}
+
+impl std::io::Read for ArgDataReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(
+            self.read_with(buf, arg_data_raw_size(), |dst, offset| unsafe {
+                ic0::msg_arg_data_copy(dst.as_mut_ptr() as i32, offset as i32, dst.len() as i32);
+            }),
+        )
+    }
+}
+
+/// Replies with the bytes passed
+pub fn reply_raw(buf: &[u8]) {
+    unsafe {
+        if !buf.is_empty() {
+            ic0::msg_reply_data_append(buf.as_ptr() as i32, buf.len() as i32)
+        };
+        ic0::msg_reply();
+    }
+}
+
+/// Returns the argument data in the current call. Traps if the data cannot be
+/// decoded.
+///
+/// Decoding follows the Candid subtyping rules, which matter when evolving
+/// an interface:
+///
+/// * Extra arguments sent by the caller are ignored.
+/// * Missing trailing arguments of type `opt`, `null` or `reserved` decode
+///   as `None`, `()` and [candid::Reserved] respectively.
+/// * An argument of type [candid::Reserved] decodes from any value, so it can
+///   be used as a placeholder for a removed argument.
+/// * An argument of type [candid::Empty] never decodes, so a method taking
+///   one can't be called.
+pub fn arg_data<R: for<'a> ArgumentDecoder<'a>>() -> R {
+    let bytes = arg_data_raw();
 
     match decode_args(&bytes) {
         Err(e) => trap(&format!("{:?}", e)),
@@ -604,6 +1771,145 @@ pub fn arg_data<R: for<'a> ArgumentDecoder<'a>>() -> R {
     }
 }
 
+/// Returns the single record argument of the current call. Traps if the
+/// data cannot be decoded.
+///
+/// An equivalent of `arg_data::<(R,)>().0`, for methods which take all
+/// their parameters as the fields of one record instead of as positional
+/// arguments. This is the shape to choose for interfaces which are expected
+/// to evolve:
+///
+/// * A record field of type `opt` can be added at any time: the messages of
+///   old callers, which don't have the field, decode it as `None`, and old
+///   callers ignore the fields they don't know in the replies.
+/// * Moving from positional arguments to a record, or back, breaks the
+///   callers: `(a, b)` is two arguments, while a record is one argument,
+///   so neither decodes as the other. Rust tuples don't help here either,
+///   as `arg_data::<((A, B),)>()` expects a single record with the fields
+///   `0` and `1`, not two arguments.
+pub fn arg_record<R: CandidType + for<'a> Deserialize<'a>>() -> R {
+    arg_data::<(R,)>().0
+}
+
+/// Replies to the current call with a single record.
+///
+/// An equivalent of `reply((record,))`, see [arg_record] for the benefits
+/// of single-record replies.
+pub fn reply_record<R: CandidType>(record: R) {
+    reply((record,))
+}
+
+// The header of a Candid message with a single argument of a primitive type:
+// the magic number, an empty type table, the number of arguments and the
+// type of the argument.
+const NAT_ARG_HEADER: &[u8] = b"DIDL\x00\x01\x7d";
+const INT_ARG_HEADER: &[u8] = b"DIDL\x00\x01\x7c";
+
+/// Encodes a single `nat` argument.
+///
+/// Produces the same bytes as `encode_args((n,))`, but skips the generic
+/// Candid serializer.
+pub fn encode_nat_arg(n: &Nat) -> Vec<u8> {
+    let mut bytes = NAT_ARG_HEADER.to_vec();
+    n.encode(&mut bytes).expect("Failed to encode nat.");
+    bytes
+}
+
+/// Encodes a single `int` argument.
+///
+/// Produces the same bytes as `encode_args((i,))`, but skips the generic
+/// Candid serializer.
+pub fn encode_int_arg(i: &Int) -> Vec<u8> {
+    let mut bytes = INT_ARG_HEADER.to_vec();
+    i.encode(&mut bytes).expect("Failed to encode int.");
+    bytes
+}
+
+/// Decodes a message whose first argument is a `nat`.
+///
+/// Messages consisting of exactly one `nat` are decoded directly, others
+/// fall back to the generic Candid deserializer.
+pub fn decode_nat_arg(bytes: &[u8]) -> Result<Nat, candid::Error> {
+    if let Some(mut rest) = bytes.strip_prefix(NAT_ARG_HEADER) {
+        if let Ok(n) = Nat::decode(&mut rest) {
+            if rest.is_empty() {
+                return Ok(n);
+            }
+        }
+    }
+    let (n,): (Nat,) = decode_args(bytes)?;
+    Ok(n)
+}
+
+/// Decodes a message whose first argument is an `int`.
+///
+/// Messages consisting of exactly one `int` are decoded directly, others
+/// fall back to the generic Candid deserializer.
+pub fn decode_int_arg(bytes: &[u8]) -> Result<Int, candid::Error> {
+    if let Some(mut rest) = bytes.strip_prefix(INT_ARG_HEADER) {
+        if let Ok(i) = Int::decode(&mut rest) {
+            if rest.is_empty() {
+                return Ok(i);
+            }
+        }
+    }
+    let (i,): (Int,) = decode_args(bytes)?;
+    Ok(i)
+}
+
+/// Returns the `nat` argument of the current call. Traps if the data cannot
+/// be decoded.
+///
+/// A faster equivalent of `arg_data::<(Nat,)>().0`.
+pub fn arg_nat() -> Nat {
+    decode_nat_arg(&arg_data_raw()).unwrap_or_else(|e| trap(&format!("{:?}", e)))
+}
+
+/// Returns the `int` argument of the current call. Traps if the data cannot
+/// be decoded.
+///
+/// A faster equivalent of `arg_data::<(Int,)>().0`.
+pub fn arg_int() -> Int {
+    decode_int_arg(&arg_data_raw()).unwrap_or_else(|e| trap(&format!("{:?}", e)))
+}
+
+/// Replies to the current call with a single `nat`.
+///
+/// A faster equivalent of `reply((n,))`.
+pub fn reply_nat(n: &Nat) {
+    std::io::Write::write_all(&mut CallReplyWriter, NAT_ARG_HEADER)
+        .expect("Could not encode reply.");
+    n.encode(&mut CallReplyWriter)
+        .expect("Could not encode reply.");
+    unsafe {
+        ic0::msg_reply();
+    }
+}
+
+/// Replies to the current call with a single `int`.
+///
+/// A faster equivalent of `reply((i,))`.
+pub fn reply_int(i: &Int) {
+    std::io::Write::write_all(&mut CallReplyWriter, INT_ARG_HEADER)
+        .expect("Could not encode reply.");
+    i.encode(&mut CallReplyWriter)
+        .expect("Could not encode reply.");
+    unsafe {
+        ic0::msg_reply();
+    }
+}
+
+/// Returns the idempotency token and the argument data of a call made with
+/// [call_idempotent]. Traps if the data cannot be decoded.
+pub fn idempotent_arg_data<R: for<'a> ArgumentDecoder<'a>>() -> (IdempotencyToken, R) {
+    let bytes = arg_data_raw();
+
+    match decode_idempotent_args(&bytes) {
+        Err(e) => trap(&format!("{:?}", e)),
+        Ok(r) => r,
+    }
+}
+
 /// Accepts the ingress message.
 pub fn accept_message() {
     unsafe {
@@ -682,6 +1988,739 @@ where
     where
         S: candid::types::Serializer,
     {
-        Err(S::Error::custom("`Empty` cannot be serialized"))
+        Err(S::Error::custom("`ManualReply` cannot be serialized"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_perform_error_codes() {
+        assert_eq!(CallPerformError::from(2), CallPerformError::SysTransient);
+        assert_eq!(
+            CallPerformError::from(2).rejection_code(),
+            RejectionCode::SysTransient
+        );
+        assert_eq!(
+            CallPerformError::from(3),
+            CallPerformError::DestinationInvalid
+        );
+        assert_eq!(CallPerformError::from(42), CallPerformError::Other(42));
+        assert_eq!(
+            CallPerformError::from(42).rejection_code(),
+            RejectionCode::Unknown
+        );
+    }
+
+    #[test]
+    fn outstanding_call_limit() {
+        assert_eq!(outstanding_call_limit_error(), None);
+        set_max_outstanding_calls(Some(1));
+        assert_eq!(outstanding_call_limit_error(), None);
+        set_max_outstanding_calls(Some(0));
+        assert_eq!(
+            outstanding_call_limit_error(),
+            Some((
+                RejectionCode::SysTransient,
+                "too many concurrent outgoing calls".to_string()
+            ))
+        );
+        set_max_outstanding_calls(None);
+        assert_eq!(outstanding_call_limit_error(), None);
+    }
+
+    #[test]
+    fn idempotent_calls_retry_transient_errors() {
+        let transient: CallResult<()> = Err((RejectionCode::SysTransient, "full".to_string()));
+        let rejected: CallResult<()> = Err((RejectionCode::CanisterReject, "no".to_string()));
+        assert!(retries_idempotent_call(&transient, 1));
+        assert!(!retries_idempotent_call(
+            &transient,
+            IDEMPOTENT_CALL_ATTEMPTS
+        ));
+        assert!(!retries_idempotent_call(&rejected, 1));
+        assert!(!retries_idempotent_call(&Ok(()), 1));
+
+        // Retrying can't help while the limit of outstanding calls is reached.
+        set_max_outstanding_calls(Some(0));
+        assert!(!retries_idempotent_call(&transient, 1));
+        set_max_outstanding_calls(None);
+    }
+
+    #[test]
+    fn notify_observer_gets_the_call_perform_outcome() {
+        thread_local! {
+            static SEEN: RefCell<Vec<(Principal, String, Result<(), CallPerformError>)>> =
+                RefCell::new(Vec::new());
+        }
+        fn observer(id: Principal, method: &str, outcome: Result<(), CallPerformError>) {
+            SEEN.with(|seen| seen.borrow_mut().push((id, method.to_string(), outcome)));
+        }
+        let id = Principal::from_slice(&[1]);
+
+        set_notify_observer(Some(observer));
+        assert_eq!(notify_outcome(id, "ping", 0), Ok(()));
+        assert_eq!(
+            notify_outcome(id, "ping", 2),
+            Err(RejectionCode::SysTransient)
+        );
+        set_notify_observer(None);
+        assert_eq!(notify_outcome(id, "ping", 0), Ok(()));
+
+        SEEN.with(|seen| {
+            assert_eq!(
+                *seen.borrow(),
+                vec![
+                    (id, "ping".to_string(), Ok(())),
+                    (id, "ping".to_string(), Err(CallPerformError::SysTransient)),
+                ]
+            )
+        });
+    }
+
+    #[test]
+    fn typed_call_keeps_the_callee() {
+        let id = Principal::from_slice(&[1]);
+        let balance = TypedCall::<(String,), (u64,)>::new(id, "balance");
+        assert_eq!(balance.id(), id);
+        assert_eq!(balance.method(), "balance");
+    }
+
+    #[test]
+    fn circuit_breaker_opens_and_half_opens() {
+        let id = Principal::from_slice(&[1]);
+        let t0 = Timestamp::from_nanos(1_000);
+        let failure: CallResult<()> = Err((RejectionCode::SysTransient, "down".to_string()));
+        let mut breaker = CircuitBreaker::new(2, Duration::from_nanos(100));
+
+        breaker.record_at(id, &failure, t0);
+        assert_eq!(breaker.state_at(&id, t0), CircuitState::Closed);
+        breaker.record_at(id, &failure, t0);
+        assert_eq!(breaker.state_at(&id, t0), CircuitState::Open);
+        assert_eq!(
+            breaker.state_at(&Principal::from_slice(&[2]), t0),
+            CircuitState::Closed
+        );
+
+        let t1 = t0 + Duration::from_nanos(100);
+        assert_eq!(breaker.state_at(&id, t1), CircuitState::HalfOpen);
+        breaker.record_at(id, &failure, t1);
+        assert_eq!(breaker.state_at(&id, t1), CircuitState::Open);
+
+        let t2 = t1 + Duration::from_nanos(100);
+        breaker.record_at(id, &Ok(()), t2);
+        assert_eq!(breaker.state_at(&id, t2), CircuitState::Closed);
+    }
+
+    #[test]
+    fn circuit_breaker_sends_a_single_probe() {
+        let id = Principal::from_slice(&[1]);
+        let t0 = Timestamp::from_nanos(1_000);
+        let failure: CallResult<()> = Err((RejectionCode::SysTransient, "down".to_string()));
+        let mut breaker = CircuitBreaker::new(1, Duration::from_nanos(100));
+        assert_eq!(breaker.admit_at(&id, t0), Some(false));
+        breaker.record_at(id, &failure, t0);
+        assert_eq!(breaker.admit_at(&id, t0), None);
+
+        let t1 = t0 + Duration::from_nanos(100);
+        assert_eq!(breaker.admit_at(&id, t1), Some(true));
+        assert_eq!(breaker.state_at(&id, t1), CircuitState::Open);
+        assert_eq!(breaker.admit_at(&id, t1), None);
+
+        // A probe which is abandoned lets the next call probe the callee.
+        breaker.abandon_probe(&id);
+        assert_eq!(breaker.state_at(&id, t1), CircuitState::HalfOpen);
+        assert_eq!(breaker.admit_at(&id, t1), Some(true));
+
+        breaker.record_at(id, &Ok(()), t1);
+        assert_eq!(breaker.admit_at(&id, t1), Some(false));
+    }
+
+    #[test]
+    fn circuit_breaker_cool_down_saturates() {
+        let id = Principal::from_slice(&[1]);
+        let now = Timestamp::from_nanos(1_000);
+        let failure: CallResult<()> = Err((RejectionCode::SysTransient, "down".to_string()));
+        let mut breaker = CircuitBreaker::new(1, Duration::MAX);
+        breaker.record_at(id, &failure, now);
+        assert_eq!(
+            breaker.state_at(&id, Timestamp::from_nanos(u64::MAX - 1)),
+            CircuitState::Open
+        );
+    }
+
+    #[test]
+    fn circuit_breaker_ignores_explicit_rejections() {
+        let id = Principal::from_slice(&[1]);
+        let now = Timestamp::from_nanos(0);
+        let rejection: CallResult<()> = Err((RejectionCode::CanisterReject, "no".to_string()));
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_at(id, &rejection, now);
+        assert_eq!(breaker.state_at(&id, now), CircuitState::Closed);
+    }
+
+    #[test]
+    fn call_cache_expires_replies() {
+        let id = Principal::from_slice(&[1]);
+        let t0 = Timestamp::from_nanos(1_000);
+        let mut cache = CallCache::new(Duration::from_nanos(100), 10);
+        assert_eq!(cache.get_at(id, "get", b"args", t0), None);
+
+        cache.insert_at(id, "get", b"args", b"reply".to_vec(), t0);
+        assert_eq!(cache.get_at(id, "get", b"args", t0), Some(&b"reply"[..]));
+        assert_eq!(cache.get_at(id, "get", b"other args", t0), None);
+        assert_eq!(cache.get_at(id, "put", b"args", t0), None);
+
+        let t1 = t0 + Duration::from_nanos(100);
+        assert_eq!(cache.get_at(id, "get", b"args", t1), None);
+        cache.insert_at(id, "put", b"args", b"reply".to_vec(), t1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn call_cache_evicts_the_oldest_reply_when_full() {
+        let id = Principal::from_slice(&[1]);
+        let now = Timestamp::from_nanos(1_000);
+        let mut cache = CallCache::new(Duration::from_secs(60), 2);
+        cache.insert_at(id, "get", b"1", b"one".to_vec(), now);
+        cache.insert_at(id, "get", b"2", b"two".to_vec(), now);
+        // Replacing a reply doesn't evict another one.
+        cache.insert_at(id, "get", b"2", b"two again".to_vec(), now);
+        assert_eq!(cache.get_at(id, "get", b"1", now), Some(&b"one"[..]));
+
+        cache.insert_at(id, "get", b"3", b"three".to_vec(), now);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_at(id, "get", b"1", now), None);
+        assert_eq!(cache.get_at(id, "get", b"2", now), Some(&b"two again"[..]));
+        assert_eq!(cache.get_at(id, "get", b"3", now), Some(&b"three"[..]));
+
+        let mut cache = CallCache::new(Duration::from_secs(60), 0);
+        cache.insert_at(id, "get", b"1", b"one".to_vec(), now);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn call_cache_ttl_saturates() {
+        let id = Principal::from_slice(&[1]);
+        let mut cache = CallCache::new(Duration::MAX, 10);
+        cache.insert_at(
+            id,
+            "get",
+            b"args",
+            b"reply".to_vec(),
+            Timestamp::from_nanos(1_000),
+        );
+        assert_eq!(
+            cache.get_at(id, "get", b"args", Timestamp::from_nanos(u64::MAX - 1)),
+            Some(&b"reply"[..])
+        );
+    }
+
+    #[test]
+    fn arg_data_reader_reads_in_chunks() {
+        let message: Vec<u8> = (0..10).collect();
+        let copy = |dst: &mut [u8], offset: usize| {
+            dst.copy_from_slice(&message[offset..offset + dst.len()]);
+        };
+        let mut reader = ArgDataReader::new();
+        let mut buf = [0; 4];
+
+        assert_eq!(reader.read_with(&mut buf, message.len(), copy), 4);
+        assert_eq!(buf, [0, 1, 2, 3]);
+        assert_eq!(reader.read_with(&mut buf, message.len(), copy), 4);
+        assert_eq!(buf, [4, 5, 6, 7]);
+        assert_eq!(reader.read_with(&mut buf, message.len(), copy), 2);
+        assert_eq!(buf[..2], [8, 9]);
+        assert_eq!(reader.read_with(&mut buf, message.len(), copy), 0);
+        assert_eq!(reader.bytes_read(), 10);
+    }
+
+    #[test]
+    fn dropped_call_future_is_not_woken() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        struct CountingWaker(AtomicUsize);
+
+        impl std::task::Wake for CountingWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let poll_pending = |state: &WasmCell<CallFutureState<()>>| {
+            let mut future = CallFuture {
+                state: state.clone(),
+            };
+            let poll = Pin::new(&mut future).poll(&mut Context::from_waker(&waker));
+            assert!(poll.is_pending());
+            future
+        };
+
+        // The reply of a pending call wakes its task.
+        let state = WasmCell::new(CallFutureState::new(Box::new(|_| Ok(()))));
+        let _future = poll_pending(&state);
+        complete(&state, Ok(()));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+
+        // The reply of a call whose future was dropped doesn't.
+        let state = WasmCell::new(CallFutureState::new(Box::new(|_| Ok(()))));
+        drop(poll_pending(&state));
+        complete(&state, Ok(()));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn select_drops_the_losing_call_future() {
+        use std::rc::Rc;
+
+        let state = WasmCell::new(CallFutureState::new(Box::new(|_| Ok(1u32))));
+        let call = CallFuture {
+            state: state.clone(),
+        };
+        let output = Rc::new(Cell::new(0));
+        let result = output.clone();
+        crate::spawn(async move {
+            let n = crate::ic_select! {
+                r = call => r.unwrap(),
+                n = async { 7 } => n,
+            };
+            result.set(n);
+        });
+        assert_eq!(output.get(), 7);
+
+        // The task is gone, so the reply of the call must not wake it.
+        assert!(state.borrow_mut().waker.is_none());
+        complete(&state, Ok(1));
+    }
+
+    #[test]
+    fn fan_out_policies() {
+        let ok = |i: u8| (Principal::from_slice(&[i]), Ok(i));
+        let err = |i: u8| {
+            (
+                Principal::from_slice(&[i]),
+                Err((RejectionCode::CanisterError, "failed".to_string())),
+            )
+        };
+        let results = || vec![ok(1), err(2), ok(3)];
+
+        let aggregated = FanOutPolicy::BestEffort.aggregate(results()).unwrap();
+        assert_eq!(
+            aggregated.successes,
+            vec![
+                (Principal::from_slice(&[1]), 1),
+                (Principal::from_slice(&[3]), 3)
+            ]
+        );
+        assert_eq!(aggregated.failures.len(), 1);
+        assert_eq!(aggregated.failures[0].0, Principal::from_slice(&[2]));
+
+        assert!(FanOutPolicy::AllOrNothing.aggregate(results()).is_err());
+        assert!(FanOutPolicy::AllOrNothing
+            .aggregate(vec![ok(1), ok(3)])
+            .is_ok());
+
+        assert!(FanOutPolicy::Quorum(2).aggregate(results()).is_ok());
+        let failed = FanOutPolicy::Quorum(3).aggregate(results()).unwrap_err();
+        assert_eq!(failed.successes.len(), 2);
+        assert_eq!(failed.failures.len(), 1);
+    }
+
+    #[test]
+    fn forbidding_cycles_rejects_payments() {
+        check_payment_allowed(100);
+        set_forbid_cycles(true);
+        check_payment_allowed(0);
+        let result = std::panic::catch_unwind(|| check_payment_allowed(100));
+        set_forbid_cycles(false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn deadline_expiry_is_classified_as_timeout() {
+        assert_eq!(RejectionCode::from(6), RejectionCode::SysUnknown);
+        assert_eq!(RejectionCode::from(7), RejectionCode::Unknown);
+        assert_eq!(RejectionCode::Unknown as i32, 7);
+        assert_eq!(
+            CallError::from((RejectionCode::SysUnknown, "deadline expired".to_string())),
+            CallError::TimedOut("deadline expired".to_string())
+        );
+        assert_eq!(
+            CallError::from((RejectionCode::SysTransient, "busy".to_string())),
+            CallError::Reject(RejectionCode::SysTransient, "busy".to_string())
+        );
+    }
+
+    #[test]
+    fn nat_fast_path_matches_generic_encoding() {
+        for n in [0u64, 1, 127, 128, 300, u64::MAX] {
+            let n = Nat::from(n);
+            let bytes = encode_nat_arg(&n);
+            assert_eq!(bytes, encode_args((n.clone(),)).unwrap());
+            assert_eq!(decode_nat_arg(&bytes).unwrap(), n);
+        }
+    }
+
+    #[test]
+    fn int_fast_path_matches_generic_encoding() {
+        for i in [0i64, 1, -1, 63, -64, 64, -65, i64::MIN, i64::MAX] {
+            let i = Int::from(i);
+            let bytes = encode_int_arg(&i);
+            assert_eq!(bytes, encode_args((i.clone(),)).unwrap());
+            assert_eq!(decode_int_arg(&bytes).unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn reserved_and_empty_edge_types() {
+        use candid::{Empty, Reserved};
+
+        let bytes = encode_args((Reserved,)).unwrap();
+        let (_,): (Reserved,) = decode_args(&bytes).unwrap();
+
+        // `reserved` accepts any value.
+        let bytes = encode_args((Nat::from(42u64), "text")).unwrap();
+        let (_, _): (Reserved, Reserved) = decode_args(&bytes).unwrap();
+
+        // Missing trailing `opt`, `null` and `reserved` arguments.
+        let bytes = encode_args(()).unwrap();
+        let (opt, (), _): (Option<u8>, (), Reserved) = decode_args(&bytes).unwrap();
+        assert_eq!(opt, None);
+
+        // Extra arguments are ignored.
+        let bytes = encode_args((1u8, 2u8)).unwrap();
+        let (n,): (u8,) = decode_args(&bytes).unwrap();
+        assert_eq!(n, 1);
+
+        // `empty` never decodes.
+        let bytes = encode_args((Reserved,)).unwrap();
+        assert!(decode_args::<(Empty,)>(&bytes).is_err());
+        assert!(decode_args::<(Empty,)>(&encode_args(()).unwrap()).is_err());
+    }
+
+    #[test]
+    fn record_argument_evolution() {
+        #[derive(CandidType, Deserialize)]
+        struct TransferV1 {
+            to: Principal,
+            amount: u64,
+        }
+
+        #[derive(CandidType, Deserialize)]
+        struct TransferV2 {
+            to: Principal,
+            amount: u64,
+            memo: Option<String>,
+        }
+
+        let to = Principal::from_slice(&[1]);
+
+        // A new `opt` field decodes as `None` from old callers.
+        let bytes = encode_args((TransferV1 { to, amount: 5 },)).unwrap();
+        let (v2,): (TransferV2,) = decode_args(&bytes).unwrap();
+        assert_eq!((v2.to, v2.amount, v2.memo), (to, 5, None));
+
+        // Old callers ignore the fields they don't know.
+        let bytes = encode_args((TransferV2 {
+            to,
+            amount: 5,
+            memo: Some("rent".to_string()),
+        },))
+        .unwrap();
+        let (v1,): (TransferV1,) = decode_args(&bytes).unwrap();
+        assert_eq!((v1.to, v1.amount), (to, 5));
+
+        // Positional arguments don't decode as a record, nor as a tuple.
+        let bytes = encode_args((to, 5u64)).unwrap();
+        assert!(decode_args::<(TransferV1,)>(&bytes).is_err());
+        assert!(decode_args::<((Principal, u64),)>(&bytes).is_err());
+    }
+
+    #[test]
+    fn manual_reply_cannot_be_serialized() {
+        let reply = ManualReply::<u64>::empty();
+        assert!(encode_args((reply,)).is_err());
+        assert_eq!(
+            <ManualReply<u64> as CandidType>::ty(),
+            <u64 as CandidType>::ty()
+        );
+    }
+
+    #[test]
+    fn idempotent_args_roundtrip() {
+        let token = IdempotencyToken(vec![1, 2, 3]);
+        let bytes = encode_idempotent_args(&token, (42u64, "text")).unwrap();
+        let (decoded_token, (n, text)): (_, (u64, String)) =
+            decode_idempotent_args(&bytes).unwrap();
+        assert_eq!(decoded_token, token);
+        assert_eq!(n, 42);
+        assert_eq!(text, "text");
+
+        assert!(decode_idempotent_args::<(u64,)>(&encode_args((42u64,)).unwrap()).is_err());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_payload_roundtrip() {
+        let text = "a highly compressible payload ".repeat(100);
+        let bytes = encode_args((text.clone(),)).unwrap();
+        let compressed = gzip(&bytes);
+        assert!(compressed.len() < bytes.len());
+
+        let limit = MAX_DECOMPRESSED_REPLY_SIZE;
+        let (decoded,): (String,) = decode_maybe_compressed(&compressed, limit).unwrap();
+        assert_eq!(decoded, text);
+        let (decoded,): (String,) = decode_maybe_compressed(&bytes, limit).unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_payload_over_the_limit() {
+        let bytes = encode_args((vec![0u8; 1 << 20],)).unwrap();
+        let compressed = gzip(&bytes);
+        assert!(compressed.len() < 1 << 12);
+
+        let limit = 1 << 16;
+        let err = decode_maybe_compressed::<(Vec<u8>,)>(&compressed, limit).unwrap_err();
+        assert!(format!("{:?}", err).contains("exceeds 65536 bytes"));
+        assert!(decode_maybe_compressed::<(Vec<u8>,)>(&compressed, bytes.len()).is_ok());
+    }
+
+    #[test]
+    fn decoding_falls_back_to_generic_path() {
+        let bytes = encode_args((Nat::from(42u64), "extra")).unwrap();
+        assert_eq!(decode_nat_arg(&bytes).unwrap(), Nat::from(42u64));
+
+        let bytes = encode_args((Int::from(-42i64), "extra")).unwrap();
+        assert_eq!(decode_int_arg(&bytes).unwrap(), Int::from(-42i64));
+
+        assert!(decode_nat_arg(&encode_args(("not a nat",)).unwrap()).is_err());
+    }
+
+    // A roundtrip and robustness harness for the decoders of untrusted
+    // payloads: the `nat`/`int` fast paths, `ArgDataReader`, gzip-compressed
+    // replies and the header of checksummed stable memory. The system API
+    // can't be called outside of a canister, so the decoders are driven with
+    // pseudo-random payloads and mutations of them. Decoding may fail, but a
+    // panic fails the test.
+    mod payload_roundtrip {
+        use super::*;
+        use crate::storage::{parse_header, HEADER_LEN};
+
+        #[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+        enum Shape {
+            Empty,
+            Point { x: i32, y: i32 },
+            Label(String),
+        }
+
+        #[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+        struct Sample {
+            n: u64,
+            i: i16,
+            flag: bool,
+            text: String,
+            blob: Vec<u8>,
+            nat: Option<Nat>,
+            owners: Vec<(Principal, Option<Shape>)>,
+        }
+
+        // xorshift64, so that failures are reproducible from the seed.
+        struct Rng(u64);
+
+        impl Rng {
+            fn next(&mut self) -> u64 {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0
+            }
+
+            fn below(&mut self, n: u64) -> u64 {
+                self.next() % n
+            }
+
+            fn bytes(&mut self, max_len: u64) -> Vec<u8> {
+                (0..self.below(max_len + 1))
+                    .map(|_| self.next() as u8)
+                    .collect()
+            }
+
+            fn text(&mut self) -> String {
+                const CHARS: &[char] = &['a', 'Z', '0', ' ', '\0', 'é', '∑', '🦀'];
+                (0..self.below(16))
+                    .map(|_| CHARS[self.below(CHARS.len() as u64) as usize])
+                    .collect()
+            }
+
+            fn shape(&mut self) -> Shape {
+                match self.below(3) {
+                    0 => Shape::Empty,
+                    1 => Shape::Point {
+                        x: self.next() as i32,
+                        y: self.next() as i32,
+                    },
+                    _ => Shape::Label(self.text()),
+                }
+            }
+
+            fn nat(&mut self) -> Nat {
+                Nat::from(self.next() as u128 * self.next() as u128)
+            }
+
+            fn int(&mut self) -> Int {
+                Int::from(self.next() as i64 as i128 * self.next() as u32 as i128)
+            }
+
+            fn sample(&mut self) -> Sample {
+                Sample {
+                    n: self.next(),
+                    i: self.next() as i16,
+                    flag: self.below(2) == 0,
+                    text: self.text(),
+                    blob: self.bytes(64),
+                    nat: match self.below(2) {
+                        0 => None,
+                        _ => Some(self.nat()),
+                    },
+                    owners: (0..self.below(4))
+                        .map(|_| {
+                            let principal = Principal::from_slice(&self.bytes(29));
+                            let shape = match self.below(2) {
+                                0 => None,
+                                _ => Some(self.shape()),
+                            };
+                            (principal, shape)
+                        })
+                        .collect(),
+                }
+            }
+        }
+
+        fn encode_reply(sample: &Sample) -> Vec<u8> {
+            let mut bytes = Vec::new();
+            write_args(&mut bytes, (sample, sample.n)).unwrap();
+            bytes
+        }
+
+        // Payloads of the shapes the decoders expect, and others.
+        fn payloads(rng: &mut Rng) -> Vec<Vec<u8>> {
+            vec![
+                encode_nat_arg(&rng.nat()),
+                encode_int_arg(&rng.int()),
+                encode_args((rng.nat(), rng.text())).unwrap(),
+                encode_reply(&rng.sample()),
+            ]
+        }
+
+        // Runs every decoder on `bytes`, checking that the ones which
+        // succeed agree with the generic Candid decoder.
+        fn decode_all(bytes: &[u8]) {
+            if let Ok(n) = decode_nat_arg(bytes) {
+                assert_eq!(decode_args::<(Nat,)>(bytes).unwrap().0, n);
+            }
+            if let Ok(i) = decode_int_arg(bytes) {
+                assert_eq!(decode_args::<(Int,)>(bytes).unwrap().0, i);
+            }
+            #[cfg(feature = "gzip")]
+            {
+                let _ = decode_maybe_compressed::<(Sample, u64)>(bytes, 1 << 16);
+                let _ = decode_maybe_compressed::<(Sample, u64)>(&gzip(bytes), 1 << 16);
+            }
+            let mut header = [0; HEADER_LEN];
+            let len = bytes.len().min(HEADER_LEN);
+            header[..len].copy_from_slice(&bytes[..len]);
+            let available = bytes.len().saturating_sub(HEADER_LEN) as u64;
+            if let Ok((len, _)) = parse_header(&header, available) {
+                assert!(len <= available);
+            }
+        }
+
+        // Reads `bytes` through an `ArgDataReader` into buffers of random
+        // sizes.
+        fn read_in_chunks(rng: &mut Rng, bytes: &[u8]) -> Vec<u8> {
+            let copy = |dst: &mut [u8], offset: usize| {
+                dst.copy_from_slice(&bytes[offset..offset + dst.len()]);
+            };
+            let mut reader = ArgDataReader::new();
+            let mut read = Vec::new();
+            loop {
+                let mut buf = vec![0; rng.below(16) as usize + 1];
+                let len = reader.read_with(&mut buf, bytes.len(), copy);
+                if len == 0 {
+                    return read;
+                }
+                read.extend_from_slice(&buf[..len]);
+            }
+        }
+
+        #[test]
+        fn random_values_roundtrip() {
+            let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+            for _ in 0..256 {
+                let n = rng.nat();
+                assert_eq!(decode_nat_arg(&encode_nat_arg(&n)).unwrap(), n);
+                let i = rng.int();
+                assert_eq!(decode_int_arg(&encode_int_arg(&i)).unwrap(), i);
+
+                let sample = rng.sample();
+                let bytes = encode_reply(&sample);
+                assert_eq!(read_in_chunks(&mut rng, &bytes), bytes);
+                #[cfg(feature = "gzip")]
+                {
+                    let (decoded, n): (Sample, u64) =
+                        decode_maybe_compressed(&gzip(&bytes), bytes.len()).unwrap();
+                    assert_eq!(decoded, sample);
+                    assert_eq!(n, sample.n);
+                }
+            }
+        }
+
+        #[test]
+        fn truncated_payloads_do_not_panic() {
+            let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+            for _ in 0..8 {
+                for bytes in payloads(&mut rng) {
+                    for len in 0..bytes.len() {
+                        decode_all(&bytes[..len]);
+                        assert!(decode_nat_arg(&bytes[..len]).is_err());
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn corrupted_payloads_do_not_panic() {
+            let mut rng = Rng(0xd1b5_4a32_d192_ed03);
+            for _ in 0..16 {
+                for mut bytes in payloads(&mut rng) {
+                    for _ in 0..16 {
+                        let index = rng.below(bytes.len() as u64) as usize;
+                        bytes[index] = rng.next() as u8;
+                        decode_all(&bytes);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn random_bytes_do_not_panic() {
+            let mut rng = Rng(0x6a09_e667_f3bc_c908);
+            for _ in 0..1024 {
+                let mut bytes = b"DIDL".to_vec();
+                bytes.extend(rng.bytes(64));
+                decode_all(&bytes);
+                decode_all(&bytes[4..]);
+                let mut stable = b"CDKS".to_vec();
+                stable.extend(rng.bytes(64));
+                decode_all(&stable);
+            }
+        }
     }
 }
@@ -5,7 +5,7 @@
 //!
 //! [1]: https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-management-canister
 
-use crate::api::call::{call, call_with_payment128, CallResult};
+use crate::api::call::{call, call_with_payment128, CallResult, RejectionCode};
 use candid::Principal;
 
 mod types;
@@ -63,6 +63,47 @@ pub async fn install_code(arg: InstallCodeArgument) -> CallResult<()> {
     call(Principal::management_canister(), "install_code", (arg,)).await
 }
 
+/// Upload a chunk of a WASM module to the chunk store of a canister.
+///
+/// WASM modules larger than the message size limit can be installed with
+/// [install_chunked_code] once all their chunks are uploaded.
+///
+/// See [IC method `upload_chunk`](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-upload_chunk).
+pub async fn upload_chunk(arg: UploadChunkArgument) -> CallResult<(ChunkHash,)> {
+    call(Principal::management_canister(), "upload_chunk", (arg,)).await
+}
+
+/// Get the hashes of the chunks in the chunk store of a canister.
+///
+/// See [IC method `stored_chunks`](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-stored_chunks).
+pub async fn stored_chunks(arg: CanisterIdRecord) -> CallResult<(Vec<ChunkHash>,)> {
+    call(Principal::management_canister(), "stored_chunks", (arg,)).await
+}
+
+/// Remove all the chunks from the chunk store of a canister.
+///
+/// See [IC method `clear_chunk_store`](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-clear_chunk_store).
+pub async fn clear_chunk_store(arg: CanisterIdRecord) -> CallResult<()> {
+    call(
+        Principal::management_canister(),
+        "clear_chunk_store",
+        (arg,),
+    )
+    .await
+}
+
+/// Install code made of chunks uploaded with [upload_chunk] into a canister.
+///
+/// See [IC method `install_chunked_code`](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-install_chunked_code).
+pub async fn install_chunked_code(arg: InstallChunkedCodeArgument) -> CallResult<()> {
+    call(
+        Principal::management_canister(),
+        "install_chunked_code",
+        (arg,),
+    )
+    .await
+}
+
 /// Remove a canister's code and state, making the canister empty again.
 ///
 /// See [IC method `uninstall_code`](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-uninstall_code)
@@ -91,6 +132,26 @@ pub async fn canister_status(arg: CanisterIdRecord) -> CallResult<(CanisterStatu
     call(Principal::management_canister(), "canister_status", (arg,)).await
 }
 
+/// Check whether a canister exists and is controlled by the caller canister.
+///
+/// This calls [canister_status], and maps its rejections with
+/// [RejectionCode::DestinationInvalid] (the canister doesn't exist) and
+/// [RejectionCode::CanisterError] to `false`. Other rejections are returned
+/// as errors.
+///
+/// Note that [canister_status] is only available to the controllers of a
+/// canister, so this returns `false` or an error for existing canisters
+/// which are not controlled by the caller canister.
+pub async fn canister_exists(canister_id: CanisterId) -> CallResult<bool> {
+    match canister_status(CanisterIdRecord { canister_id }).await {
+        Ok(_) => Ok(true),
+        Err((RejectionCode::DestinationInvalid, _)) | Err((RejectionCode::CanisterError, _)) => {
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
 /// Delete a canister from the IC.
 ///
 /// See [IC method `delete_canister`](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-delete_canister)
@@ -76,6 +76,47 @@ pub struct InstallCodeArgument {
     pub arg: Vec<u8>,
 }
 
+/// Argument type of [upload_chunk](super::upload_chunk).
+#[derive(
+    CandidType, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone,
+)]
+pub struct UploadChunkArgument {
+    /// Principle of the canister whose chunk store receives the chunk.
+    pub canister_id: CanisterId,
+    /// A chunk of a WASM module, of at most 1MiB.
+    pub chunk: Vec<u8>,
+}
+
+/// The SHA-256 hash of a chunk in a chunk store.
+#[derive(
+    CandidType, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone,
+)]
+pub struct ChunkHash {
+    /// The hash of the chunk.
+    pub hash: Vec<u8>,
+}
+
+/// Argument type of [install_chunked_code](super::install_chunked_code).
+#[derive(
+    CandidType, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone,
+)]
+pub struct InstallChunkedCodeArgument {
+    /// See [CanisterInstallMode].
+    pub mode: CanisterInstallMode,
+    /// Principle of the canister to install the code into.
+    pub target_canister: CanisterId,
+    /// Principle of the canister whose chunk store holds the chunks.
+    ///
+    /// `None` means the chunk store of the target canister.
+    pub store_canister: Option<CanisterId>,
+    /// The hashes of the chunks making up the WASM module, in order.
+    pub chunk_hashes_list: Vec<ChunkHash>,
+    /// The SHA-256 hash of the whole WASM module.
+    pub wasm_module_hash: Vec<u8>,
+    /// The argument to be passed to `canister_init` or `canister_post_upgrade`.
+    pub arg: Vec<u8>,
+}
+
 /// A wrapper of canister id.
 #[derive(
     CandidType, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy,
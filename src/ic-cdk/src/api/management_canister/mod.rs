@@ -7,6 +7,16 @@
 //! * For those calls require cycles payments, they are handled behind-the-scenes.
 //! * Handle the response ergonomically.
 //!
+//! # Routing
+//!
+//! Unlike ingress messages, calls from a canister to the management canister
+//! don't carry an effective canister id. The system routes them by itself:
+//! calls which create a canister (such as [main::create_canister] and
+//! [provisional::provisional_create_canister_with_cycles]) are executed on
+//! the subnet of the caller, and the other calls are routed to the subnet of
+//! the `canister_id` in their argument. These functions thus take no routing
+//! hint.
+//!
 //! [1]: https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-management-canister
 //! [2]: https://internetcomputer.org/assets/files/ic-a45d11feb0ba0494055083f9d2d21ddf.did
 
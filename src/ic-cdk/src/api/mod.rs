@@ -1,28 +1,87 @@
 //! System API and low level functions for it.
 use candid::Principal;
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::convert::TryFrom;
 
 pub mod call;
 pub mod management_canister;
 pub mod stable;
+pub mod time;
+
+thread_local! {
+    static MAX_LOG_MESSAGE_LEN: Cell<Option<usize>> = Cell::new(None);
+    static PRE_TRAP_HOOK: Cell<Option<fn()>> = Cell::new(None);
+}
+
+/// Caps the length of the messages passed to [print] and [trap].
+///
+/// Messages longer than `max` bytes are cut at the last character boundary
+/// within `max` bytes and followed by `...`. `None` (the default) keeps the
+/// messages whole.
+pub fn set_max_log_message_len(max: Option<usize>) {
+    MAX_LOG_MESSAGE_LEN.with(|m| m.set(max));
+}
+
+fn truncate_log_message(message: &str, max: Option<usize>) -> Cow<'_, str> {
+    match max {
+        Some(max) if message.len() > max => {
+            let mut end = max;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            Cow::Owned(format!("{}...", &message[..end]))
+        }
+        _ => Cow::Borrowed(message),
+    }
+}
 
 /// Prints the given message.
+///
+/// See [set_max_log_message_len] to cap the length of the message.
 pub fn print<S: std::convert::AsRef<str>>(s: S) {
-    let s = s.as_ref();
+    let s = truncate_log_message(s.as_ref(), MAX_LOG_MESSAGE_LEN.with(|m| m.get()));
     unsafe {
         ic0::debug_print(s.as_ptr() as i32, s.len() as i32);
     }
 }
 
+/// Sets a function which [trap] runs before aborting.
+///
+/// A trap rolls back the state of the canister without running any
+/// destructor, so logs buffered on the heap are lost. The hook gets a
+/// chance to [print] them first. `None` (the default) removes the hook.
+///
+/// The hook runs at most once per trap: if it traps itself, the nested
+/// trap doesn't run it again.
+pub fn set_pre_trap_hook(hook: Option<fn()>) {
+    PRE_TRAP_HOOK.with(|h| h.set(hook));
+}
+
 /// Traps with the given message.
+///
+/// Runs the hook set with [set_pre_trap_hook], if any, first.
+/// See [set_max_log_message_len] to cap the length of the message.
 pub fn trap(message: &str) -> ! {
+    run_pre_trap_hook();
+    let message = truncate_log_message(message, MAX_LOG_MESSAGE_LEN.with(|m| m.get()));
     unsafe {
         ic0::trap(message.as_ptr() as i32, message.len() as i32);
     }
     unreachable!()
 }
 
-/// Get current timestamp
+// Removes the hook before running it, so that a trap in the hook doesn't
+// run it again. The trap rolls back the removal along with everything else.
+fn run_pre_trap_hook() {
+    if let Some(hook) = PRE_TRAP_HOOK.with(|h| h.take()) {
+        hook();
+    }
+}
+
+/// Get current timestamp, in nanoseconds since the UNIX epoch.
+///
+/// See [time::Timestamp] for a typed alternative.
 pub fn time() -> u64 {
     unsafe { ic0::time() as u64 }
 }
@@ -37,6 +96,21 @@ pub fn caller() -> Principal {
     Principal::try_from(&bytes).unwrap()
 }
 
+/// Returns true if the caller of the current call is the anonymous principal.
+pub fn caller_is_anonymous() -> bool {
+    caller() == Principal::anonymous()
+}
+
+/// Traps if the caller of the current call is the anonymous principal.
+///
+/// Useful as the first statement of methods that require an authenticated
+/// caller.
+pub fn reject_anonymous() {
+    if caller_is_anonymous() {
+        trap("anonymous caller not allowed");
+    }
+}
+
 /// Returns the canister id as a blob.
 pub fn id() -> Principal {
     let len: u32 = unsafe { ic0::canister_self_size() as u32 };
@@ -47,6 +121,24 @@ pub fn id() -> Principal {
     Principal::try_from(&bytes).unwrap()
 }
 
+/// Returns the id of the subnet the canister is running on.
+///
+/// Only available with the `subnet-self` feature. Canisters using this
+/// function import the `ic0.subnet_self_*` system calls, and replicas which
+/// don't provide them refuse to install such canisters.
+///
+/// Traps if the system returns bytes which are not a valid principal.
+#[cfg(feature = "subnet-self")]
+pub fn subnet_self() -> Principal {
+    let len: u32 = unsafe { ic0::subnet_self_size() as u32 };
+    let mut bytes = vec![0u8; len as usize];
+    unsafe {
+        ic0::subnet_self_copy(bytes.as_mut_ptr() as i32, 0, len as i32);
+    }
+    Principal::try_from(&bytes)
+        .unwrap_or_else(|e| trap(&format!("invalid subnet id {:?}: {}", bytes, e)))
+}
+
 /// Get the amount of funds available in the canister.
 pub fn canister_balance() -> u64 {
     unsafe { ic0::canister_cycle_balance() as u64 }
@@ -98,6 +190,21 @@ pub fn data_certificate() -> Option<Vec<u8>> {
     Some(buf)
 }
 
+/// Returns true if the current message is executed by all the replicas of
+/// the subnet, false if it's executed by a single replica.
+///
+/// This is derived from the availability of the data certificate: it returns
+/// false exactly when [data_certificate] returns `Some`. That's the case for
+/// query calls made by users, which run on a single replica and whose
+/// effects are discarded. Update calls, and query methods executed as part
+/// of an update call or called by other canisters, are replicated.
+///
+/// Composite queries don't get a data certificate, so they are reported as
+/// replicated even though they run on a single replica.
+pub fn is_replicated_execution() -> bool {
+    unsafe { ic0::data_certificate_present() == 0 }
+}
+
 /// Returns the number of instructions that the canister executed since the last [entry
 /// point](https://internetcomputer.org/docs/current/references/ic-interface-spec/#entry-points).
 #[inline]
@@ -113,3 +220,50 @@ pub fn instruction_counter() -> u64 {
 pub fn performance_counter(counter_type: u32) -> u64 {
     unsafe { ic0::performance_counter(counter_type as i32) as u64 }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_long_log_messages() {
+        assert_eq!(truncate_log_message("hello", None), "hello");
+        assert_eq!(truncate_log_message("hello", Some(5)), "hello");
+        assert_eq!(truncate_log_message("hello world", Some(5)), "hello...");
+        // "é" takes two bytes, the cut doesn't split it.
+        assert_eq!(truncate_log_message("héllo", Some(2)), "h...");
+    }
+
+    #[test]
+    fn pre_trap_hook_runs_at_most_once() {
+        thread_local! {
+            static RUNS: Cell<u32> = Cell::new(0);
+        }
+        fn hook() {
+            RUNS.with(|runs| runs.set(runs.get() + 1));
+        }
+        // A hook which traps, running the hook of the nested trap.
+        fn trapping_hook() {
+            hook();
+            run_pre_trap_hook();
+        }
+        let runs = || RUNS.with(|runs| runs.get());
+
+        run_pre_trap_hook();
+        assert_eq!(runs(), 0);
+
+        set_pre_trap_hook(Some(hook));
+        run_pre_trap_hook();
+        run_pre_trap_hook();
+        assert_eq!(runs(), 1);
+
+        set_pre_trap_hook(Some(hook));
+        set_pre_trap_hook(None);
+        run_pre_trap_hook();
+        assert_eq!(runs(), 1);
+
+        set_pre_trap_hook(Some(trapping_hook));
+        run_pre_trap_hook();
+        assert_eq!(runs(), 2);
+    }
+}
@@ -0,0 +1,92 @@
+use super::*;
+
+/// A backup of a region of stable memory which can be restored later.
+///
+/// Creating a checkpoint copies the bytes of the region to a scratch
+/// region. If the code mutating the region (e.g. a migration) detects an
+/// inconsistency, [StableCheckpoint::rollback] copies the backup over the
+/// region again. Otherwise, [StableCheckpoint::commit] discards the backup.
+///
+/// Note that the scratch region is overwritten, and stable memory is grown
+/// if the scratch region ends beyond its current size.
+pub struct StableCheckpoint<M: StableMemory = CanisterStableMemory> {
+    /// The offset of the region.
+    offset: u64,
+
+    /// The length of the region, in bytes.
+    len: u64,
+
+    /// The offset of the scratch region holding the backup.
+    backup_offset: u64,
+
+    /// The stable memory the regions belong to.
+    memory: M,
+}
+
+impl StableCheckpoint {
+    /// Backs up `len` bytes of the stable memory starting at `offset` to the
+    /// scratch region starting at `backup_offset`.
+    pub fn new(offset: u64, len: u64, backup_offset: u64) -> Result<Self, StableMemoryError> {
+        Self::with_memory(CanisterStableMemory::default(), offset, len, backup_offset)
+    }
+}
+
+impl<M: StableMemory> StableCheckpoint<M> {
+    /// Creates a new `StableCheckpoint` which backs up a region of the selected memory.
+    ///
+    /// Returns an error if the region exceeds the current size of the memory,
+    /// if either region ends past the 64-bit address space, or if the memory
+    /// can't be grown to fit the scratch region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scratch region overlaps the backed up region.
+    pub fn with_memory(
+        memory: M,
+        offset: u64,
+        len: u64,
+        backup_offset: u64,
+    ) -> Result<Self, StableMemoryError> {
+        let (end, backup_end) = match (offset.checked_add(len), backup_offset.checked_add(len)) {
+            (Some(end), Some(backup_end)) => (end, backup_end),
+            _ => return Err(StableMemoryError::OutOfBounds),
+        };
+        assert!(
+            backup_offset >= end || backup_end <= offset,
+            "the scratch region overlaps the checkpoint region"
+        );
+        let size_bytes = memory.stable64_size() * WASM_PAGE_SIZE_IN_BYTES as u64;
+        if end > size_bytes {
+            return Err(StableMemoryError::OutOfBounds);
+        }
+        ensure_size(&memory, backup_end)?;
+        copy(&memory, offset, backup_offset, len);
+        Ok(Self {
+            offset,
+            len,
+            backup_offset,
+            memory,
+        })
+    }
+
+    /// Keeps the changes made to the region since the checkpoint was created.
+    pub fn commit(self) {}
+
+    /// Restores the region to the state it had when the checkpoint was created.
+    pub fn rollback(self) {
+        copy(&self.memory, self.backup_offset, self.offset, self.len);
+    }
+}
+
+/// Copies `len` bytes from `src` to `dst` one page at a time.
+fn copy<M: StableMemory>(memory: &M, src: u64, dst: u64, len: u64) {
+    let mut buf = vec![0; WASM_PAGE_SIZE_IN_BYTES.min(len as usize)];
+    let mut copied = 0;
+    while copied < len {
+        let chunk_len = (len - copied).min(buf.len() as u64) as usize;
+        let chunk = &mut buf[..chunk_len];
+        memory.stable64_read(src + copied, chunk);
+        memory.stable64_write(dst + copied, chunk);
+        copied += chunk_len as u64;
+    }
+}
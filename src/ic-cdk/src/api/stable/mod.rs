@@ -3,10 +3,16 @@
 //! You can check the [Internet Computer Specification](https://smartcontracts.org/docs/interface-spec/index.html#system-api-stable-memory)
 //! for a in-depth explanation of stable memory.
 mod canister;
+mod checkpoint;
+mod principal_set;
+mod ring_buffer;
 #[cfg(test)]
 mod tests;
 
 pub use canister::CanisterStableMemory;
+pub use checkpoint::StableCheckpoint;
+pub use principal_set::{PrincipalSetError, StablePrincipalSet};
+pub use ring_buffer::{RingBufferError, StableRingBuffer};
 use std::{error, fmt, io};
 
 const WASM_PAGE_SIZE_IN_BYTES: usize = 64 * 1024; // 64KB
@@ -116,6 +122,17 @@ pub fn stable64_read(offset: u64, buf: &mut [u8]) {
     CANISTER_STABLE_MEMORY.stable64_read(offset, buf)
 }
 
+/// Grows the memory so that it's at least `size_bytes` large.
+fn ensure_size<M: StableMemory>(memory: &M, size_bytes: u64) -> Result<(), StableMemoryError> {
+    let page_size = WASM_PAGE_SIZE_IN_BYTES as u64;
+    let required_pages = (size_bytes + page_size - 1) / page_size;
+    let current_pages = memory.stable64_size();
+    if required_pages > current_pages {
+        memory.stable64_grow(required_pages - current_pages)?;
+    }
+    Ok(())
+}
+
 /// Returns a copy of the stable memory.
 ///
 /// This will map the whole memory (even if not all of it has been written to).
@@ -205,6 +222,18 @@ impl<M: StableMemory> io::Write for StableWriter<M> {
     }
 }
 
+impl<M: StableMemory> io::Seek for StableWriter<M> {
+    /// Moves the offset of the next write.
+    ///
+    /// The offset can't be moved past the end of the allocated stable memory,
+    /// [SeekFrom::End](io::SeekFrom::End) being relative to that end.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let capacity_bytes = self.capacity as usize * WASM_PAGE_SIZE_IN_BYTES;
+        self.offset = seek_offset(self.offset, capacity_bytes, pos)?;
+        Ok(self.offset as u64)
+    }
+}
+
 /// A writer to the stable memory which first writes the bytes to an in memory buffer and flushes
 /// the buffer to stable memory each time it becomes full.
 ///
@@ -243,6 +272,13 @@ impl<M: StableMemory> io::Write for BufferedStableWriter<M> {
     }
 }
 
+impl<M: StableMemory> io::Seek for BufferedStableWriter<M> {
+    /// Flushes the buffer, then moves the offset of the next write.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
 /// A reader to the stable memory.
 ///
 /// Keeps an offset and reads off stable memory consecutively.
@@ -306,6 +342,19 @@ impl<M: StableMemory> io::Read for StableReader<M> {
     }
 }
 
+impl<M: StableMemory> io::Seek for StableReader<M> {
+    /// Moves the offset of the next read.
+    ///
+    /// The offset can't be moved past the end of the stable memory, as its
+    /// size was when the reader was created. [SeekFrom::End](io::SeekFrom::End)
+    /// is relative to that end.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let capacity_bytes = self.capacity as usize * WASM_PAGE_SIZE_IN_BYTES;
+        self.offset = seek_offset(self.offset, capacity_bytes, pos)?;
+        Ok(self.offset as u64)
+    }
+}
+
 /// A reader to the stable memory which reads bytes a chunk at a time as each chunk is required.
 pub struct BufferedStableReader<M: StableMemory = CanisterStableMemory> {
     inner: io::BufReader<StableReader<M>>,
@@ -332,3 +381,26 @@ impl<M: StableMemory> io::Read for BufferedStableReader<M> {
         self.inner.read(buf)
     }
 }
+
+impl<M: StableMemory> io::Seek for BufferedStableReader<M> {
+    /// Discards the buffer, then moves the offset of the next read.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+// Resolves a seek from `offset` within a memory of `capacity_bytes`.
+fn seek_offset(offset: usize, capacity_bytes: usize, pos: io::SeekFrom) -> io::Result<usize> {
+    let target = match pos {
+        io::SeekFrom::Start(n) => n as i128,
+        io::SeekFrom::End(n) => capacity_bytes as i128 + n as i128,
+        io::SeekFrom::Current(n) => offset as i128 + n as i128,
+    };
+    if target < 0 || target > capacity_bytes as i128 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            StableMemoryError::OutOfBounds,
+        ));
+    }
+    Ok(target as usize)
+}
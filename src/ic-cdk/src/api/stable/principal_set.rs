@@ -0,0 +1,237 @@
+use super::*;
+use candid::Principal;
+use std::collections::HashMap;
+
+// The header holds the capacity and the number of elements, as
+// little-endian u64 values.
+const HEADER_SIZE: u64 = 16;
+
+// Principals are at most 29 bytes long. Each slot holds the length of the
+// principal as a single byte, followed by the principal padded with zeros.
+const MAX_PRINCIPAL_LEN: usize = 29;
+const SLOT_SIZE: u64 = 1 + MAX_PRINCIPAL_LEN as u64;
+
+/// An error when inserting into a [StablePrincipalSet].
+#[derive(Debug)]
+pub enum PrincipalSetError {
+    /// The set already holds as many principals as its capacity.
+    Full {
+        /// The capacity of the set.
+        capacity: u64,
+    },
+}
+
+impl fmt::Display for PrincipalSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Full { capacity } => {
+                write!(f, "the principal set is full ({} principals)", capacity)
+            }
+        }
+    }
+}
+
+impl error::Error for PrincipalSetError {}
+
+/// A set of principals stored in stable memory, such as the admins or the
+/// allowed callers of a canister.
+///
+/// The principals are stored unordered, in slots of 30 bytes: a length byte
+/// followed by the principal, which is at most 29 bytes long. Removing a
+/// principal moves the last one into its slot, so inserting or removing a
+/// principal writes a single slot and the header, whatever the size of the
+/// set. The set takes `16 + capacity * 30` bytes starting at its offset,
+/// and [StablePrincipalSet::insert] fails once it holds `capacity`
+/// principals.
+///
+/// [StablePrincipalSet::load] builds an index of the principals on the heap,
+/// so [StablePrincipalSet::contains], e.g. in a guard checking the caller,
+/// doesn't read the stable memory.
+pub struct StablePrincipalSet<M: StableMemory = CanisterStableMemory> {
+    memory: M,
+    offset: u64,
+    capacity: u64,
+    principals: Vec<Principal>,
+    slots: HashMap<Principal, u64>,
+}
+
+impl StablePrincipalSet {
+    /// Creates an empty set at `offset` in the stable memory, overwriting
+    /// any data there.
+    pub fn init(offset: u64, capacity: u64) -> Result<Self, StableMemoryError> {
+        Self::init_with_memory(CanisterStableMemory::default(), offset, capacity)
+    }
+
+    /// Loads a set previously created at `offset` in the stable memory.
+    pub fn load(offset: u64) -> Result<Self, StableMemoryError> {
+        Self::load_with_memory(CanisterStableMemory::default(), offset)
+    }
+}
+
+impl<M: StableMemory> StablePrincipalSet<M> {
+    /// Creates an empty set at `offset` in the selected memory, overwriting
+    /// any data there.
+    ///
+    /// Returns an error if the memory can't be grown to fit the set.
+    pub fn init_with_memory(
+        memory: M,
+        offset: u64,
+        capacity: u64,
+    ) -> Result<Self, StableMemoryError> {
+        let end = set_end(offset, capacity).ok_or(StableMemoryError::OutOfMemory)?;
+        ensure_size(&memory, end)?;
+        let set = Self {
+            memory,
+            offset,
+            capacity,
+            principals: Vec::new(),
+            slots: HashMap::new(),
+        };
+        set.write_header();
+        Ok(set)
+    }
+
+    /// Loads a set previously created at `offset` in the selected memory.
+    ///
+    /// Returns [StableMemoryError::OutOfBounds] if the header at `offset`
+    /// doesn't describe a set fitting in the memory, e.g. because no set
+    /// was created there.
+    pub fn load_with_memory(memory: M, offset: u64) -> Result<Self, StableMemoryError> {
+        let size_bytes = memory.stable64_size() * WASM_PAGE_SIZE_IN_BYTES as u64;
+        if !matches!(offset.checked_add(HEADER_SIZE), Some(end) if end <= size_bytes) {
+            return Err(StableMemoryError::OutOfBounds);
+        }
+        let mut header = [0; HEADER_SIZE as usize];
+        memory.stable64_read(offset, &mut header);
+        let field = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&header[i * 8..(i + 1) * 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let capacity = field(0);
+        let len = field(1);
+        if len > capacity || !matches!(set_end(offset, capacity), Some(end) if end <= size_bytes) {
+            return Err(StableMemoryError::OutOfBounds);
+        }
+        let mut set = Self {
+            memory,
+            offset,
+            capacity,
+            principals: Vec::with_capacity(len as usize),
+            slots: HashMap::with_capacity(len as usize),
+        };
+        for slot in 0..len {
+            let principal = set.read_slot(slot);
+            set.slots.insert(principal, slot);
+            set.principals.push(principal);
+        }
+        Ok(set)
+    }
+
+    /// Returns the maximum number of principals.
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Returns the number of principals.
+    pub fn len(&self) -> u64 {
+        self.principals.len() as u64
+    }
+
+    /// Returns true if the set has no principals.
+    pub fn is_empty(&self) -> bool {
+        self.principals.is_empty()
+    }
+
+    /// Returns true if the set contains `principal`.
+    pub fn contains(&self, principal: &Principal) -> bool {
+        self.slots.contains_key(principal)
+    }
+
+    /// Adds a principal to the set.
+    ///
+    /// Returns whether the principal was newly inserted, or an error if the
+    /// set is full.
+    pub fn insert(&mut self, principal: Principal) -> Result<bool, PrincipalSetError> {
+        if self.contains(&principal) {
+            return Ok(false);
+        }
+        if self.len() == self.capacity {
+            return Err(PrincipalSetError::Full {
+                capacity: self.capacity,
+            });
+        }
+        let slot = self.len();
+        self.write_slot(slot, &principal);
+        self.slots.insert(principal, slot);
+        self.principals.push(principal);
+        self.write_header();
+        Ok(true)
+    }
+
+    /// Removes a principal from the set.
+    ///
+    /// Returns whether the principal was present. The last principal is
+    /// moved to the slot of the removed one, so the order of
+    /// [StablePrincipalSet::iter] is not preserved.
+    pub fn remove(&mut self, principal: &Principal) -> bool {
+        let slot = match self.slots.remove(principal) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        self.principals.swap_remove(slot as usize);
+        if let Some(moved) = self.principals.get(slot as usize).copied() {
+            self.write_slot(slot, &moved);
+            self.slots.insert(moved, slot);
+        }
+        self.write_header();
+        true
+    }
+
+    /// Removes all the principals.
+    pub fn clear(&mut self) {
+        self.principals.clear();
+        self.slots.clear();
+        self.write_header();
+    }
+
+    /// Returns an iterator over the principals, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Principal> + '_ {
+        self.principals.iter()
+    }
+
+    fn write_header(&self) {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        for field in [self.capacity, self.len()] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        self.memory.stable64_write(self.offset, &header);
+    }
+
+    fn slot_offset(&self, slot: u64) -> u64 {
+        self.offset + HEADER_SIZE + slot * SLOT_SIZE
+    }
+
+    fn write_slot(&self, slot: u64, principal: &Principal) {
+        let bytes = principal.as_slice();
+        let mut buf = [0; SLOT_SIZE as usize];
+        buf[0] = bytes.len() as u8;
+        buf[1..=bytes.len()].copy_from_slice(bytes);
+        self.memory.stable64_write(self.slot_offset(slot), &buf);
+    }
+
+    fn read_slot(&self, slot: u64) -> Principal {
+        let mut buf = [0; SLOT_SIZE as usize];
+        self.memory.stable64_read(self.slot_offset(slot), &mut buf);
+        let len = (buf[0] as usize).min(MAX_PRINCIPAL_LEN);
+        Principal::from_slice(&buf[1..=len])
+    }
+}
+
+// Returns the offset of the end of a set of the given capacity, or `None` if
+// it doesn't fit in a 64-bit address space.
+fn set_end(offset: u64, capacity: u64) -> Option<u64> {
+    capacity
+        .checked_mul(SLOT_SIZE)
+        .and_then(|slots| offset.checked_add(HEADER_SIZE)?.checked_add(slots))
+}
@@ -0,0 +1,242 @@
+use super::*;
+use candid::{CandidType, Deserialize};
+use std::marker::PhantomData;
+
+// The header holds the capacity, the slot size, the index of the oldest
+// element and the number of elements, as little-endian u64 values.
+const HEADER_SIZE: u64 = 32;
+
+// Each slot holds the length of the encoded element as a little-endian u32,
+// followed by the encoded element.
+const SLOT_LEN_SIZE: u64 = 4;
+
+/// An error when pushing to a [StableRingBuffer].
+#[derive(Debug)]
+pub enum RingBufferError {
+    /// The value could not be encoded.
+    Encode(candid::Error),
+    /// The encoded value doesn't fit in a slot.
+    ValueTooLarge {
+        /// The size of the encoded value.
+        size: usize,
+        /// The size of a slot.
+        slot_size: usize,
+    },
+}
+
+impl fmt::Display for RingBufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Encode(e) => write!(f, "failed to encode value: {}", e),
+            Self::ValueTooLarge { size, slot_size } => write!(
+                f,
+                "encoded value of {} bytes exceeds the slot size of {} bytes",
+                size, slot_size
+            ),
+        }
+    }
+}
+
+impl error::Error for RingBufferError {}
+
+/// A fixed-capacity queue of Candid-encoded values stored in stable memory.
+///
+/// Pushing to a full buffer overwrites the oldest element. As the buffer
+/// lives in stable memory, it survives upgrades without being serialized
+/// in `pre_upgrade`: call [StableRingBuffer::load] in `post_upgrade` to get
+/// it back.
+///
+/// Every element takes a slot of a fixed size chosen on creation, so the
+/// buffer takes `32 + capacity * (4 + slot_size)` bytes of stable memory
+/// starting at its offset.
+pub struct StableRingBuffer<T, M: StableMemory = CanisterStableMemory> {
+    memory: M,
+    offset: u64,
+    capacity: u64,
+    slot_size: u64,
+    start: u64,
+    len: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> StableRingBuffer<T> {
+    /// Creates an empty buffer at `offset` in the stable memory, overwriting
+    /// any data there.
+    pub fn init(offset: u64, capacity: u64, slot_size: u64) -> Result<Self, StableMemoryError> {
+        Self::init_with_memory(CanisterStableMemory::default(), offset, capacity, slot_size)
+    }
+
+    /// Loads a buffer previously created at `offset` in the stable memory.
+    pub fn load(offset: u64) -> Result<Self, StableMemoryError> {
+        Self::load_with_memory(CanisterStableMemory::default(), offset)
+    }
+}
+
+impl<T, M: StableMemory> StableRingBuffer<T, M> {
+    /// Creates an empty buffer at `offset` in the selected memory,
+    /// overwriting any data there.
+    ///
+    /// Returns [StableMemoryError::OutOfMemory] if `slot_size` exceeds
+    /// `u32::MAX` bytes, or if the memory can't be grown to fit the buffer.
+    pub fn init_with_memory(
+        memory: M,
+        offset: u64,
+        capacity: u64,
+        slot_size: u64,
+    ) -> Result<Self, StableMemoryError> {
+        assert!(
+            capacity > 0,
+            "the capacity of a ring buffer must be positive"
+        );
+        let end = buffer_end(offset, capacity, slot_size).ok_or(StableMemoryError::OutOfMemory)?;
+        ensure_size(&memory, end)?;
+        let buffer = Self {
+            memory,
+            offset,
+            capacity,
+            slot_size,
+            start: 0,
+            len: 0,
+            _marker: PhantomData,
+        };
+        buffer.write_header();
+        Ok(buffer)
+    }
+
+    /// Loads a buffer previously created at `offset` in the selected memory.
+    ///
+    /// Returns [StableMemoryError::OutOfBounds] if the header at `offset`
+    /// doesn't describe a buffer fitting in the memory, e.g. because no
+    /// buffer was created there.
+    pub fn load_with_memory(memory: M, offset: u64) -> Result<Self, StableMemoryError> {
+        let size_bytes = memory.stable64_size() * WASM_PAGE_SIZE_IN_BYTES as u64;
+        if !matches!(offset.checked_add(HEADER_SIZE), Some(end) if end <= size_bytes) {
+            return Err(StableMemoryError::OutOfBounds);
+        }
+        let mut header = [0; HEADER_SIZE as usize];
+        memory.stable64_read(offset, &mut header);
+        let field = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&header[i * 8..(i + 1) * 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let (capacity, slot_size, start, len) = (field(0), field(1), field(2), field(3));
+        if capacity == 0 || start >= capacity || len > capacity {
+            return Err(StableMemoryError::OutOfBounds);
+        }
+        if !matches!(buffer_end(offset, capacity, slot_size), Some(end) if end <= size_bytes) {
+            return Err(StableMemoryError::OutOfBounds);
+        }
+        Ok(Self {
+            memory,
+            offset,
+            capacity,
+            slot_size,
+            start,
+            len,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns the maximum number of elements.
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the buffer has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Removes all the elements.
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+        self.write_header();
+    }
+
+    fn write_header(&self) {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        for field in [self.capacity, self.slot_size, self.start, self.len] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        self.memory.stable64_write(self.offset, &header);
+    }
+
+    fn slot_offset(&self, slot: u64) -> u64 {
+        self.offset + HEADER_SIZE + slot * (SLOT_LEN_SIZE + self.slot_size)
+    }
+}
+
+impl<T, M> StableRingBuffer<T, M>
+where
+    T: CandidType + for<'de> Deserialize<'de>,
+    M: StableMemory,
+{
+    /// Appends an element, overwriting the oldest one if the buffer is full.
+    pub fn push(&mut self, value: &T) -> Result<(), RingBufferError> {
+        let bytes = candid::encode_one(value).map_err(RingBufferError::Encode)?;
+        if bytes.len() as u64 > self.slot_size {
+            return Err(RingBufferError::ValueTooLarge {
+                size: bytes.len(),
+                slot_size: self.slot_size as usize,
+            });
+        }
+        let slot = (self.start + self.len) % self.capacity;
+        let slot_offset = self.slot_offset(slot);
+        self.memory
+            .stable64_write(slot_offset, &(bytes.len() as u32).to_le_bytes());
+        self.memory
+            .stable64_write(slot_offset + SLOT_LEN_SIZE, &bytes);
+        if self.len == self.capacity {
+            self.start = (self.start + 1) % self.capacity;
+        } else {
+            self.len += 1;
+        }
+        self.write_header();
+        Ok(())
+    }
+
+    /// Returns the element at `index`, counting from the oldest one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the element can't be decoded as `T`.
+    pub fn get(&self, index: u64) -> Option<T> {
+        if index >= self.len {
+            return None;
+        }
+        let slot_offset = self.slot_offset((self.start + index) % self.capacity);
+        let mut len_bytes = [0; SLOT_LEN_SIZE as usize];
+        self.memory.stable64_read(slot_offset, &mut len_bytes);
+        let mut bytes = vec![0; u32::from_le_bytes(len_bytes) as usize];
+        self.memory
+            .stable64_read(slot_offset + SLOT_LEN_SIZE, &mut bytes);
+        match candid::decode_one(&bytes) {
+            Ok(value) => Some(value),
+            Err(e) => panic!("failed to decode ring buffer element {}: {}", index, e),
+        }
+    }
+
+    /// Returns an iterator over the elements, from the oldest to the newest.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).filter_map(move |index| self.get(index))
+    }
+}
+
+// Returns the offset of the end of a buffer with the given layout, or `None`
+// if the length of an element, stored as a u32, can't reach the slot size or
+// the buffer doesn't fit in a 64-bit address space.
+fn buffer_end(offset: u64, capacity: u64, slot_size: u64) -> Option<u64> {
+    if slot_size > u32::MAX as u64 {
+        return None;
+    }
+    capacity
+        .checked_mul(SLOT_LEN_SIZE + slot_size)
+        .and_then(|slots| offset.checked_add(HEADER_SIZE)?.checked_add(slots))
+}
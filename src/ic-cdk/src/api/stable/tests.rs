@@ -80,7 +80,7 @@ fn pages_required(bytes_len: usize) -> usize {
 mod stable_writer_tests {
     use super::*;
     use rstest::rstest;
-    use std::io::Write;
+    use std::io::{Seek, SeekFrom, Write};
 
     #[rstest]
     #[case(None)]
@@ -153,6 +153,33 @@ mod stable_writer_tests {
         assert_eq!(capacity_pages, min_pages_required as u64);
     }
 
+    #[rstest]
+    #[case(None)]
+    #[case(Some(10))]
+    fn seek_then_write(#[case] buffer_size: Option<usize>) {
+        let memory = Rc::new(Mutex::new(vec![0; 100]));
+        let writer = StableWriter::with_memory(TestStableMemory::new(memory.clone()), 0);
+        let mut writer: Box<dyn SeekWrite> = match buffer_size {
+            Some(buffer_size) => Box::new(BufferedStableWriter::with_writer(buffer_size, writer)),
+            None => Box::new(writer),
+        };
+
+        writer.write_all(&[1; 4]).unwrap();
+        assert_eq!(writer.seek(SeekFrom::Start(50)).unwrap(), 50);
+        writer.write_all(&[2; 4]).unwrap();
+        assert_eq!(writer.seek(SeekFrom::Current(-8)).unwrap(), 46);
+        writer.write_all(&[3; 2]).unwrap();
+        assert!(writer.seek(SeekFrom::End(1)).is_err());
+        writer.flush().unwrap();
+
+        let result = &*memory.lock().unwrap();
+        assert_eq!(result[..5], [1, 1, 1, 1, 0]);
+        assert_eq!(result[46..54], [3, 3, 0, 0, 2, 2, 2, 2]);
+    }
+
+    trait SeekWrite: Write + Seek {}
+    impl<T: Write + Seek> SeekWrite for T {}
+
     fn build_writer(memory: TestStableMemory, buffer_size: Option<usize>) -> Box<dyn Write> {
         let writer = StableWriter::with_memory(memory, 0);
         if let Some(buffer_size) = buffer_size {
@@ -166,7 +193,7 @@ mod stable_writer_tests {
 mod stable_reader_tests {
     use super::*;
     use rstest::rstest;
-    use std::io::Read;
+    use std::io::{Read, Seek, SeekFrom};
 
     #[rstest]
     #[case(None)]
@@ -185,6 +212,35 @@ mod stable_reader_tests {
         assert_eq!(input, output[..input.len()]);
     }
 
+    #[rstest]
+    #[case(None)]
+    #[case(Some(10))]
+    fn seek_then_read(#[case] buffer_size: Option<usize>) {
+        let input: Vec<u8> = (0..100).collect();
+        let memory = Rc::new(Mutex::new(input));
+        let reader = StableReader::with_memory(TestStableMemory::new(memory), 0);
+        let mut reader: Box<dyn SeekRead> = match buffer_size {
+            Some(buffer_size) => Box::new(BufferedStableReader::with_reader(buffer_size, reader)),
+            None => Box::new(reader),
+        };
+
+        let mut buf = [0; 3];
+        reader.seek(SeekFrom::Start(40)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [40, 41, 42]);
+        reader.seek(SeekFrom::Current(-13)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [30, 31, 32]);
+
+        let end = WASM_PAGE_SIZE_IN_BYTES as u64;
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), end);
+        assert!(reader.seek(SeekFrom::Start(end + 1)).is_err());
+        assert!(reader.seek(SeekFrom::Current(-(end as i64) - 1)).is_err());
+    }
+
+    trait SeekRead: Read + Seek {}
+    impl<T: Read + Seek> SeekRead for T {}
+
     fn build_reader(memory: TestStableMemory, buffer_size: Option<usize>) -> Box<dyn Read> {
         let reader = StableReader::with_memory(memory, 0);
         if let Some(buffer_size) = buffer_size {
@@ -194,3 +250,291 @@ mod stable_reader_tests {
         }
     }
 }
+
+mod stable_checkpoint_tests {
+    use super::*;
+
+    fn region(memory: &Rc<Mutex<Vec<u8>>>, offset: usize, len: usize) -> Vec<u8> {
+        memory.lock().unwrap()[offset..offset + len].to_vec()
+    }
+
+    #[test]
+    fn rollback_restores_region() {
+        let memory = Rc::new(Mutex::new(vec![1; 1000]));
+        let checkpoint =
+            StableCheckpoint::with_memory(TestStableMemory::new(memory.clone()), 100, 200, 2000)
+                .unwrap();
+
+        TestStableMemory::new(memory.clone()).stable64_write(150, &[7; 100]);
+        assert_eq!(region(&memory, 150, 100), vec![7; 100]);
+
+        checkpoint.rollback();
+        assert_eq!(region(&memory, 100, 200), vec![1; 200]);
+    }
+
+    #[test]
+    fn commit_keeps_changes() {
+        let memory = Rc::new(Mutex::new(vec![1; 1000]));
+        let checkpoint =
+            StableCheckpoint::with_memory(TestStableMemory::new(memory.clone()), 0, 500, 500)
+                .unwrap();
+
+        TestStableMemory::new(memory.clone()).stable64_write(0, &[7; 500]);
+        checkpoint.commit();
+        assert_eq!(region(&memory, 0, 500), vec![7; 500]);
+    }
+
+    #[test]
+    fn grows_memory_for_backup() {
+        let memory = Rc::new(Mutex::new(vec![1; 1000]));
+        let backup_offset = 3 * WASM_PAGE_SIZE_IN_BYTES as u64;
+        let checkpoint = StableCheckpoint::with_memory(
+            TestStableMemory::new(memory.clone()),
+            0,
+            1000,
+            backup_offset,
+        )
+        .unwrap();
+
+        assert_eq!(TestStableMemory::new(memory.clone()).stable64_size(), 4);
+        assert_eq!(region(&memory, backup_offset as usize, 1000), vec![1; 1000]);
+        checkpoint.commit();
+    }
+
+    #[test]
+    fn fails_on_region_out_of_bounds() {
+        let memory = Rc::new(Mutex::new(vec![1; 1000]));
+        let result = StableCheckpoint::with_memory(
+            TestStableMemory::new(memory),
+            0,
+            2 * WASM_PAGE_SIZE_IN_BYTES as u64,
+            4 * WASM_PAGE_SIZE_IN_BYTES as u64,
+        );
+        assert!(matches!(result, Err(StableMemoryError::OutOfBounds)));
+    }
+
+    #[test]
+    fn fails_on_overflowing_regions() {
+        let memory = Rc::new(Mutex::new(vec![1; 1000]));
+        for (offset, len, backup_offset) in [(u64::MAX, 2, 0), (0, 2, u64::MAX), (1, u64::MAX, 0)] {
+            let result = StableCheckpoint::with_memory(
+                TestStableMemory::new(memory.clone()),
+                offset,
+                len,
+                backup_offset,
+            );
+            assert!(matches!(result, Err(StableMemoryError::OutOfBounds)));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps")]
+    fn panics_on_overlapping_regions() {
+        let memory = Rc::new(Mutex::new(vec![1; 1000]));
+        let _ = StableCheckpoint::with_memory(TestStableMemory::new(memory), 0, 500, 400);
+    }
+}
+
+mod stable_ring_buffer_tests {
+    use super::*;
+
+    #[test]
+    fn push_overwrites_oldest() {
+        let memory = Rc::new(Mutex::new(Vec::new()));
+        let mut buffer =
+            StableRingBuffer::<u64, _>::init_with_memory(TestStableMemory::new(memory), 0, 3, 16)
+                .unwrap();
+        assert!(buffer.is_empty());
+
+        for i in 0..5u64 {
+            buffer.push(&i).unwrap();
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(buffer.get(0), Some(2));
+        assert_eq!(buffer.get(3), None);
+    }
+
+    #[test]
+    fn survives_reload() {
+        let memory = Rc::new(Mutex::new(Vec::new()));
+        let mut buffer = StableRingBuffer::<String, _>::init_with_memory(
+            TestStableMemory::new(memory.clone()),
+            100,
+            4,
+            32,
+        )
+        .unwrap();
+        for event in ["a", "b", "c", "d", "e"] {
+            buffer.push(&event.to_string()).unwrap();
+        }
+
+        let buffer =
+            StableRingBuffer::<String, _>::load_with_memory(TestStableMemory::new(memory), 100)
+                .unwrap();
+        assert_eq!(buffer.capacity(), 4);
+        assert_eq!(buffer.iter().collect::<Vec<_>>(), vec!["b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn load_rejects_invalid_headers() {
+        let is_rejected = |memory: Vec<u8>| {
+            matches!(
+                StableRingBuffer::<u64, _>::load_with_memory(
+                    TestStableMemory::new(Rc::new(Mutex::new(memory))),
+                    0
+                ),
+                Err(StableMemoryError::OutOfBounds)
+            )
+        };
+        let header = |fields: [u64; 4]| {
+            let mut bytes = vec![0; WASM_PAGE_SIZE_IN_BYTES];
+            for (i, field) in fields.iter().enumerate() {
+                bytes[i * 8..(i + 1) * 8].copy_from_slice(&field.to_le_bytes());
+            }
+            bytes
+        };
+
+        // No memory at all, or a zeroed region.
+        assert!(is_rejected(Vec::new()));
+        assert!(is_rejected(header([0; 4])));
+        // The start or the length exceed the capacity.
+        assert!(is_rejected(header([4, 16, 4, 0])));
+        assert!(is_rejected(header([4, 16, 0, 5])));
+        // The slots extend past the end of the memory.
+        assert!(is_rejected(header([4, 1 << 20, 0, 0])));
+        assert!(is_rejected(header([u64::MAX, 16, 0, 0])));
+        assert!(!is_rejected(header([4, 16, 3, 4])));
+    }
+
+    #[test]
+    fn init_rejects_what_load_would() {
+        for (capacity, slot_size) in [(4, u32::MAX as u64 + 1), (u64::MAX, 16)] {
+            let memory = Rc::new(Mutex::new(Vec::new()));
+            assert!(matches!(
+                StableRingBuffer::<u64, _>::init_with_memory(
+                    TestStableMemory::new(memory),
+                    0,
+                    capacity,
+                    slot_size
+                ),
+                Err(StableMemoryError::OutOfMemory)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_values_larger_than_slot() {
+        let memory = Rc::new(Mutex::new(Vec::new()));
+        let mut buffer =
+            StableRingBuffer::<String, _>::init_with_memory(TestStableMemory::new(memory), 0, 2, 8)
+                .unwrap();
+        assert!(matches!(
+            buffer.push(&"too long for a slot".to_string()),
+            Err(RingBufferError::ValueTooLarge { .. })
+        ));
+        assert!(buffer.is_empty());
+    }
+}
+
+mod stable_principal_set_tests {
+    use super::*;
+    use candid::Principal;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id; 29])
+    }
+
+    #[test]
+    fn insert_remove_contains() {
+        let memory = Rc::new(Mutex::new(Vec::new()));
+        let mut set =
+            StablePrincipalSet::init_with_memory(TestStableMemory::new(memory), 0, 4).unwrap();
+        assert!(set.is_empty());
+
+        assert!(set.insert(principal(1)).unwrap());
+        assert!(set.insert(Principal::anonymous()).unwrap());
+        assert!(!set.insert(principal(1)).unwrap());
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&principal(1)));
+
+        assert!(set.remove(&principal(1)));
+        assert!(!set.remove(&principal(1)));
+        assert!(!set.contains(&principal(1)));
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![&Principal::anonymous()]
+        );
+    }
+
+    #[test]
+    fn survives_reload() {
+        let memory = Rc::new(Mutex::new(Vec::new()));
+        let mut set =
+            StablePrincipalSet::init_with_memory(TestStableMemory::new(memory.clone()), 100, 8)
+                .unwrap();
+        for id in 1..=5 {
+            set.insert(principal(id)).unwrap();
+        }
+        set.remove(&principal(2));
+        set.insert(Principal::management_canister()).unwrap();
+
+        let set = StablePrincipalSet::load_with_memory(TestStableMemory::new(memory), 100).unwrap();
+        assert_eq!(set.capacity(), 8);
+        assert_eq!(set.len(), 5);
+        assert!(!set.contains(&principal(2)));
+        assert!(set.contains(&principal(5)));
+        assert!(set.contains(&Principal::management_canister()));
+    }
+
+    #[test]
+    fn load_rejects_invalid_headers() {
+        let is_rejected = |memory: Vec<u8>| {
+            matches!(
+                StablePrincipalSet::load_with_memory(
+                    TestStableMemory::new(Rc::new(Mutex::new(memory))),
+                    0
+                ),
+                Err(StableMemoryError::OutOfBounds)
+            )
+        };
+        let header = |fields: [u64; 2]| {
+            let mut bytes = vec![0; WASM_PAGE_SIZE_IN_BYTES];
+            for (i, field) in fields.iter().enumerate() {
+                bytes[i * 8..(i + 1) * 8].copy_from_slice(&field.to_le_bytes());
+            }
+            bytes
+        };
+
+        // No memory at all.
+        assert!(is_rejected(Vec::new()));
+        // The length exceeds the capacity.
+        assert!(is_rejected(header([4, 5])));
+        // The slots extend past the end of the memory.
+        assert!(is_rejected(header([1 << 20, 0])));
+        assert!(is_rejected(header([u64::MAX, u64::MAX])));
+        assert!(!is_rejected(header([4, 4])));
+    }
+
+    #[test]
+    fn init_rejects_overflowing_capacity() {
+        let memory = Rc::new(Mutex::new(Vec::new()));
+        assert!(matches!(
+            StablePrincipalSet::init_with_memory(TestStableMemory::new(memory), 0, u64::MAX),
+            Err(StableMemoryError::OutOfMemory)
+        ));
+    }
+
+    #[test]
+    fn rejects_inserts_when_full() {
+        let memory = Rc::new(Mutex::new(Vec::new()));
+        let mut set =
+            StablePrincipalSet::init_with_memory(TestStableMemory::new(memory), 0, 1).unwrap();
+        set.insert(principal(1)).unwrap();
+        assert!(matches!(
+            set.insert(principal(2)),
+            Err(PrincipalSetError::Full { capacity: 1 })
+        ));
+        assert!(!set.insert(principal(1)).unwrap());
+    }
+}
@@ -0,0 +1,176 @@
+//! Typed timestamps and deadlines.
+//!
+//! [time](fn@super::time) returns the current time as a raw `u64` number of
+//! nanoseconds since the UNIX epoch. The types in this module keep the unit
+//! explicit and do arithmetic with [Duration].
+use std::convert::TryFrom;
+use std::ops::{Add, Sub};
+use std::time::Duration;
+
+/// A point in time, in nanoseconds since 1970-01-01 UTC.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// The UNIX epoch.
+    pub const UNIX_EPOCH: Self = Self(0);
+
+    /// Returns the current time, see [time](fn@super::time).
+    pub fn now() -> Self {
+        Self(super::time())
+    }
+
+    /// Creates a timestamp from nanoseconds since the UNIX epoch.
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    /// Returns the number of nanoseconds since the UNIX epoch.
+    pub const fn as_nanos(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time elapsed from `earlier` to this timestamp, or zero if
+    /// `earlier` is later than this timestamp.
+    pub fn elapsed_since(self, earlier: Timestamp) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Returns the time elapsed since this timestamp.
+    pub fn elapsed(self) -> Duration {
+        Self::now().elapsed_since(self)
+    }
+
+    /// Returns `self + duration`, or None on overflow.
+    pub fn checked_add(self, duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|nanos| self.0.checked_add(nanos))
+            .map(Self)
+    }
+
+    /// Returns `self - duration`, or None if the result would precede the
+    /// UNIX epoch.
+    pub fn checked_sub(self, duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|nanos| self.0.checked_sub(nanos))
+            .map(Self)
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    /// # Panics
+    ///
+    /// Panics on overflow.
+    fn add(self, duration: Duration) -> Timestamp {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to timestamp")
+    }
+}
+
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    /// # Panics
+    ///
+    /// Panics if the result would precede the UNIX epoch.
+    fn sub(self, duration: Duration) -> Timestamp {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from timestamp")
+    }
+}
+
+impl Sub<Timestamp> for Timestamp {
+    type Output = Duration;
+
+    /// Same as [Timestamp::elapsed_since].
+    fn sub(self, earlier: Timestamp) -> Duration {
+        self.elapsed_since(earlier)
+    }
+}
+
+impl From<Timestamp> for u64 {
+    fn from(timestamp: Timestamp) -> u64 {
+        timestamp.0
+    }
+}
+
+/// A point in time by which something must happen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Timestamp);
+
+impl Deadline {
+    /// Creates a deadline at the given time.
+    pub const fn at(timestamp: Timestamp) -> Self {
+        Self(timestamp)
+    }
+
+    /// Creates a deadline `duration` after the current time.
+    pub fn after(duration: Duration) -> Self {
+        Self(Timestamp::now() + duration)
+    }
+
+    /// Returns the time of the deadline.
+    pub const fn timestamp(self) -> Timestamp {
+        self.0
+    }
+
+    /// Returns true if the deadline is past at the given time.
+    pub fn is_past_at(self, now: Timestamp) -> bool {
+        now >= self.0
+    }
+
+    /// Returns true if the deadline is past.
+    pub fn is_past(self) -> bool {
+        self.is_past_at(Timestamp::now())
+    }
+
+    /// Returns the time left until the deadline at the given time, or zero if
+    /// the deadline is past.
+    pub fn remaining_at(self, now: Timestamp) -> Duration {
+        self.0.elapsed_since(now)
+    }
+
+    /// Returns the time left until the deadline, or zero if it is past.
+    pub fn remaining(self) -> Duration {
+        self.remaining_at(Timestamp::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_arithmetic() {
+        let t = Timestamp::from_nanos(1_000_000_000);
+        assert_eq!((t + Duration::from_millis(1)).as_nanos(), 1_001_000_000);
+        assert_eq!(t - Duration::from_secs(1), Timestamp::UNIX_EPOCH);
+        assert_eq!(t - Timestamp::UNIX_EPOCH, Duration::from_secs(1));
+        assert_eq!(Timestamp::UNIX_EPOCH.elapsed_since(t), Duration::ZERO);
+        assert_eq!(t.checked_sub(Duration::from_secs(2)), None);
+        assert_eq!(
+            Timestamp::from_nanos(u64::MAX).checked_add(Duration::from_nanos(1)),
+            None
+        );
+        assert_eq!(t.checked_add(Duration::MAX), None);
+    }
+
+    #[test]
+    fn deadline() {
+        let deadline = Deadline::at(Timestamp::from_nanos(1_000));
+        assert!(!deadline.is_past_at(Timestamp::from_nanos(999)));
+        assert!(deadline.is_past_at(Timestamp::from_nanos(1_000)));
+        assert_eq!(
+            deadline.remaining_at(Timestamp::from_nanos(400)),
+            Duration::from_nanos(600)
+        );
+        assert_eq!(
+            deadline.remaining_at(Timestamp::from_nanos(2_000)),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Assembles the Candid interface of the canister at runtime.
+//!
+//! Handlers are registered with their method name, kind and Candid types,
+//! and [export_service] renders the registered methods as a `.did` service.
+//! Tooling such as `dfx` extracts the interface of a running canister by
+//! calling the `__get_candid_interface_tmp_hack` query, which can be exposed
+//! as follows:
+//!
+//! ```rust,no_run
+//! use ic_cdk::candid_service::{export_service, register_method, MethodKind};
+//!
+//! fn register_methods() {
+//!     register_method::<(String,), (String,)>("greet", MethodKind::Query);
+//!     register_method::<(u64,), ()>("set_counter", MethodKind::Update);
+//! }
+//!
+//! // #[init]
+//! fn canister_init() {
+//!     register_methods();
+//! }
+//!
+//! // #[post_upgrade]
+//! fn canister_post_upgrade() {
+//!     register_methods();
+//! }
+//!
+//! // #[query(name = "__get_candid_interface_tmp_hack")]
+//! fn export_candid() -> String {
+//!     export_service()
+//! }
+//! ```
+//!
+//! The registry lives on the heap, so it has to be filled again in
+//! `post_upgrade`.
+
+use candid::parser::types::FuncMode;
+use candid::types::internal::{Function, Type, TypeContainer};
+use candid::CandidType;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+/// The kind of a canister method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MethodKind {
+    /// A method exported with `#[query]`.
+    Query,
+    /// A method exported with `#[update]`.
+    Update,
+    /// An update method whose reply is ignored by its callers.
+    Oneway,
+}
+
+/// The Candid types of the arguments or results of a method.
+///
+/// It's implemented for tuples of up to 16 [CandidType]s, each element of
+/// the tuple being one argument or result.
+pub trait CandidTypes {
+    /// Adds the types to `env` and returns them in order.
+    fn types(env: &mut TypeContainer) -> Vec<Type>;
+}
+
+macro_rules! candid_types_impl {
+    ($($ty:ident)*) => {
+        impl<$($ty: CandidType),*> CandidTypes for ($($ty,)*) {
+            #[allow(unused_variables)]
+            fn types(env: &mut TypeContainer) -> Vec<Type> {
+                vec![$(env.add::<$ty>()),*]
+            }
+        }
+    };
+}
+
+candid_types_impl!();
+candid_types_impl!(A);
+candid_types_impl!(A B);
+candid_types_impl!(A B C);
+candid_types_impl!(A B C D);
+candid_types_impl!(A B C D E);
+candid_types_impl!(A B C D E F);
+candid_types_impl!(A B C D E F G);
+candid_types_impl!(A B C D E F G H);
+candid_types_impl!(A B C D E F G H I);
+candid_types_impl!(A B C D E F G H I J);
+candid_types_impl!(A B C D E F G H I J K);
+candid_types_impl!(A B C D E F G H I J K L);
+candid_types_impl!(A B C D E F G H I J K L M);
+candid_types_impl!(A B C D E F G H I J K L M N);
+candid_types_impl!(A B C D E F G H I J K L M N O);
+candid_types_impl!(A B C D E F G H I J K L M N O P);
+
+type TypesFn = fn(&mut TypeContainer) -> Vec<Type>;
+
+struct MethodEntry {
+    kind: MethodKind,
+    args: TypesFn,
+    rets: TypesFn,
+}
+
+thread_local! {
+    static METHODS: RefCell<BTreeMap<String, MethodEntry>> = RefCell::new(BTreeMap::new());
+    static INIT_ARGS: RefCell<Option<TypesFn>> = RefCell::new(None);
+}
+
+/// Registers a method of the canister.
+///
+/// `A` and `R` are tuples of the argument and result types of the method.
+/// Registering a method with the same name again replaces it.
+pub fn register_method<A: CandidTypes, R: CandidTypes>(name: &str, kind: MethodKind) {
+    let entry = MethodEntry {
+        kind,
+        args: A::types,
+        rets: R::types,
+    };
+    METHODS.with(|methods| methods.borrow_mut().insert(name.to_string(), entry));
+}
+
+/// Registers the arguments of the `init` method, which become the
+/// arguments of the service.
+pub fn register_init_args<A: CandidTypes>() {
+    INIT_ARGS.with(|init| *init.borrow_mut() = Some(A::types));
+}
+
+/// Removes all the registered methods and init arguments.
+pub fn clear_registry() {
+    METHODS.with(|methods| methods.borrow_mut().clear());
+    INIT_ARGS.with(|init| *init.borrow_mut() = None);
+}
+
+/// Renders the registered methods as a Candid service.
+pub fn export_service() -> String {
+    let mut env = TypeContainer::new();
+    let service = METHODS.with(|methods| {
+        methods
+            .borrow()
+            .iter()
+            .map(|(name, entry)| {
+                let modes = match entry.kind {
+                    MethodKind::Query => vec![FuncMode::Query],
+                    MethodKind::Update => vec![],
+                    MethodKind::Oneway => vec![FuncMode::Oneway],
+                };
+                let func = Function {
+                    modes,
+                    args: (entry.args)(&mut env),
+                    rets: (entry.rets)(&mut env),
+                };
+                (name.clone(), Type::Func(func))
+            })
+            .collect::<Vec<_>>()
+    });
+    let service = Type::Service(service);
+    let actor = match INIT_ARGS.with(|init| *init.borrow()) {
+        Some(init_args) => Type::Class(init_args(&mut env), Box::new(service)),
+        None => service,
+    };
+    env.env.0.insert("S".to_string(), actor);
+    candid::bindings::candid::compile(&env.env, &Some(Type::Var("S".to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_registered_methods() {
+        clear_registry();
+        register_method::<(String,), (String,)>("greet", MethodKind::Query);
+        register_method::<(u64, bool), ()>("set", MethodKind::Update);
+        register_method::<(), ()>("ping", MethodKind::Oneway);
+
+        let did = export_service();
+        assert!(did.contains("greet : (text) -> (text) query;"), "{}", did);
+        assert!(did.contains("set : (nat64, bool) -> ();"), "{}", did);
+        assert!(did.contains("ping : () -> () oneway;"), "{}", did);
+    }
+}
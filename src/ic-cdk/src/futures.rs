@@ -1,7 +1,9 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::atomic::AtomicBool;
-use std::task::Context;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
 
 /// Must be called on every top-level future corresponding to a method call of a
 /// canister by the IC.
@@ -19,38 +21,260 @@ use std::task::Context;
 /// API requires us to pass one thin pointer, while a a pointer to a `dyn Trait`
 /// can only be fat. So we create one additional thin pointer, pointing to the
 /// fat pointer and pass it instead.
+///
+/// Tasks are polled from a first-in, first-out queue: a task woken while
+/// another one is being polled, and a task spawned from within another
+/// task, are polled once the tasks queued before them were. So a task which
+/// keeps spawning or waking other tasks can't starve the tasks that were
+/// woken first. Outside of a task, e.g. in a non-async method, the spawned
+/// future is polled before `spawn` returns.
 pub fn spawn<F: 'static + Future<Output = ()>>(future: F) {
     let future_ptr = Box::into_raw(Box::new(future));
     let future_ptr_ptr: *mut *mut dyn Future<Output = ()> = Box::into_raw(Box::new(future_ptr));
-    let mut pinned_future = unsafe { Pin::new_unchecked(&mut *future_ptr) };
-    if pinned_future
-        .as_mut()
-        .poll(&mut Context::from_waker(&waker::waker(
-            future_ptr_ptr as *const (),
-        )))
-        .is_ready()
-    {
-        unsafe {
-            let _ = Box::from_raw(future_ptr);
-            let _ = Box::from_raw(future_ptr_ptr);
+    schedule(future_ptr_ptr as *const ());
+}
+
+thread_local! {
+    // The tasks waiting to be polled, in the order in which they were woken.
+    static READY: RefCell<VecDeque<*const ()>> = RefCell::new(VecDeque::new());
+    // Whether the ready tasks are being polled.
+    static POLLING: Cell<bool> = Cell::new(false);
+    // The functions to run before the ready tasks are polled next, see
+    // [after_cleanup].
+    static AFTER_CLEANUP: RefCell<Vec<Box<dyn FnOnce()>>> = RefCell::new(Vec::new());
+}
+
+// Queues a task and polls the ready tasks, unless they're already being
+// polled further up the stack.
+fn schedule(task: *const ()) {
+    READY.with(|ready| {
+        let mut ready = ready.borrow_mut();
+        if !ready.contains(&task) {
+            ready.push_back(task);
+        }
+    });
+    if POLLING.with(|polling| polling.replace(true)) {
+        return;
+    }
+    // Stop polling if a task panics, and forget the tasks which were queued
+    // behind it, so that the next message starts afresh. They're polled again
+    // if they're woken again.
+    struct Polling;
+    impl Drop for Polling {
+        fn drop(&mut self) {
+            READY.with(|ready| ready.borrow_mut().clear());
+            POLLING.with(|polling| polling.set(false));
+        }
+    }
+    let _polling = Polling;
+    if !in_cleanup() {
+        for f in AFTER_CLEANUP.with(|after| after.take()) {
+            f();
+        }
+    }
+    while let Some(task) = READY.with(|ready| ready.borrow_mut().pop_front()) {
+        unsafe { waker::poll(task) };
+    }
+}
+
+// Removes a task which completed or was dropped from the queue.
+fn unschedule(task: *const ()) {
+    READY.with(|ready| ready.borrow_mut().retain(|t| *t != task));
+}
+
+/// The status of a task spawned with [spawn_isolated](crate::spawn_isolated).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// The task is waiting for a call to complete.
+    Running,
+    /// The task ran to completion.
+    Completed,
+    /// The task panicked with the given message and was dropped.
+    Panicked(String),
+    /// The task was dropped before completing, e.g. by the cleanup callback
+    /// after it trapped in a reply callback.
+    Aborted,
+}
+
+/// A handle to observe the status of a task spawned with
+/// [spawn_isolated](crate::spawn_isolated).
+#[derive(Clone, Debug)]
+pub struct TaskHandle {
+    status: Rc<RefCell<TaskStatus>>,
+}
+
+impl TaskHandle {
+    /// Returns the current status of the task.
+    pub fn status(&self) -> TaskStatus {
+        self.status.borrow().clone()
+    }
+}
+
+/// Wraps a future so that a panic while polling it marks the task as
+/// failed instead of unwinding into the executor.
+struct CatchUnwind<F> {
+    future: F,
+    status: Rc<RefCell<TaskStatus>>,
+}
+
+impl<F: Future<Output = ()>> Future for CatchUnwind<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        // SAFETY: `future` is never moved out of the pinned wrapper.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        match poll_catch_unwind(future, context) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(())) => {
+                *this.status.borrow_mut() = TaskStatus::Completed;
+                Poll::Ready(())
+            }
+            Err(message) => {
+                *this.status.borrow_mut() = TaskStatus::Panicked(message);
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl<F> Drop for CatchUnwind<F> {
+    fn drop(&mut self) {
+        let mut status = self.status.borrow_mut();
+        if *status == TaskStatus::Running {
+            *status = TaskStatus::Aborted;
         }
     }
 }
 
-pub(crate) static CLEANUP: AtomicBool = AtomicBool::new(false);
+#[cfg(panic = "unwind")]
+fn poll_catch_unwind<F: Future<Output = ()>>(
+    future: Pin<&mut F>,
+    context: &mut Context<'_>,
+) -> Result<Poll<()>, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| future.poll(context))).map_err(
+        |payload| match payload.downcast_ref::<&'static str>() {
+            Some(s) => s.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(s) => s.clone(),
+                None => "Box<Any>".to_string(),
+            },
+        },
+    )
+}
+
+#[cfg(not(panic = "unwind"))]
+fn poll_catch_unwind<F: Future<Output = ()>>(
+    future: Pin<&mut F>,
+    context: &mut Context<'_>,
+) -> Result<Poll<()>, String> {
+    Ok(future.poll(context))
+}
+
+/// Like [spawn], but a panic while polling the future only fails this task.
+///
+/// See [spawn_isolated](crate::spawn_isolated) for the limits of the isolation.
+pub fn spawn_isolated<F: 'static + Future<Output = ()>>(future: F) -> TaskHandle {
+    let status = Rc::new(RefCell::new(TaskStatus::Running));
+    spawn(CatchUnwind {
+        future,
+        status: status.clone(),
+    });
+    TaskHandle { status }
+}
+
+/// A future resolving to the output of the first of `futures` to complete.
+///
+/// The other futures are dropped as soon as one completes. Used by
+/// [ic_select](crate::ic_select).
+pub struct SelectFirst<'a, T> {
+    futures: Vec<Pin<Box<dyn Future<Output = T> + 'a>>>,
+}
+
+impl<'a, T> SelectFirst<'a, T> {
+    /// Selects over the given futures.
+    pub fn new(futures: Vec<Pin<Box<dyn Future<Output = T> + 'a>>>) -> Self {
+        Self { futures }
+    }
+
+    /// Boxes a future to pass it to [SelectFirst::new].
+    pub fn boxed(future: impl Future<Output = T> + 'a) -> Pin<Box<dyn Future<Output = T> + 'a>> {
+        Box::pin(future)
+    }
+}
+
+impl<'a, T> Future for SelectFirst<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        let ready =
+            this.futures
+                .iter_mut()
+                .find_map(|future| match future.as_mut().poll(context) {
+                    Poll::Ready(output) => Some(output),
+                    Poll::Pending => None,
+                });
+        match ready {
+            Some(output) => {
+                // The system still owns the reference to the call state passed
+                // to the callbacks of a dropped call future, which is released
+                // when the reply arrives. Dropping the future clears the waker
+                // in that state, so the reply doesn't wake this task, which may
+                // be gone by then.
+                this.futures.clear();
+                Poll::Ready(output)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// The output of a branch of [ic_select](crate::ic_select): the output of
+/// the `i`-th branch is wrapped in `This` and `i` `Next`.
+#[doc(hidden)]
+pub enum Branch<T, U> {
+    This(T),
+    Next(U),
+}
+
+thread_local! {
+    // Whether woken tasks are dropped instead of polled, while recovering
+    // from a callback trap.
+    static CLEANUP: Cell<bool> = Cell::new(false);
+}
+
+/// Wakes the task of `waker` so that it is dropped without being polled
+/// again, e.g. because the callback the task was waiting for trapped.
+pub(crate) fn wake_for_cleanup(waker: Waker) {
+    CLEANUP.with(|cleanup| cleanup.set(true));
+    waker.wake();
+    CLEANUP.with(|cleanup| cleanup.set(false));
+}
+
+/// Runs `f` before the ready tasks are polled next, i.e. in the next message
+/// which runs a task. A cleanup callback can't poll tasks, so this is how it
+/// wakes the tasks which must run again, e.g. to retry the work of the task
+/// it dropped.
+pub(crate) fn after_cleanup(f: impl FnOnce() + 'static) {
+    AFTER_CLEANUP.with(|after| after.borrow_mut().push(Box::new(f)));
+}
+
+/// Returns true while the task of a trapped callback is being dropped.
+pub(crate) fn in_cleanup() -> bool {
+    CLEANUP.with(|cleanup| cleanup.get())
+}
 
 // This module contains the implementation of a waker we're using for waking
-// top-level futures (the ones returned by canister methods). The waker polls
-// the future once and re-pins it on the heap, if it's pending. If the future is
-// done, we do nothing. Hence, it will be unallocated once we exit the scope and
-// we're not interested in the result, as it can only be a unit `()` if the
-// waker was used as intended.
+// top-level futures (the ones returned by canister methods). Waking a future
+// queues it, and it is polled once all the futures queued before it were
+// (see [spawn]). When polled, the future is re-pinned on the heap if it's
+// pending. If the future is done, we do nothing. Hence, it will be
+// unallocated once we exit the scope and we're not interested in the
+// result, as it can only be a unit `()` if the waker was used as intended.
 mod waker {
     use super::*;
-    use std::{
-        sync::atomic::Ordering,
-        task::{RawWaker, RawWakerVTable, Waker},
-    };
+    use std::task::{RawWaker, RawWakerVTable};
     type FuturePtr = *mut dyn Future<Output = ()>;
 
     static MY_VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
@@ -63,33 +287,246 @@ mod waker {
         raw_waker(ptr)
     }
 
-    // Our waker will be called only if one of the response callbacks is triggered.
-    // Then, the waker will restore the future from the pointer we passed into the
-    // waker inside the `kickstart` method and poll the future again. If the future
-    // is pending, we leave it on the heap. If it's ready, we deallocate the
-    // pointer. If CLEANUP is set, then we're recovering from a callback trap, and
-    // want to drop the future without executing any more of it.
+    // Our waker will be called if one of the response callbacks is triggered,
+    // or by a future which wants to be polled again. Then, the waker queues
+    // the future we passed into the waker inside `spawn`. If CLEANUP is set,
+    // then we're recovering from a callback trap, and want to drop the future
+    // right away without executing any more of it.
     unsafe fn wake(ptr: *const ()) {
+        if super::in_cleanup() {
+            super::unschedule(ptr);
+            let boxed_future_ptr_ptr = Box::from_raw(ptr as *mut FuturePtr);
+            let _ = Box::from_raw(*boxed_future_ptr_ptr);
+        } else {
+            super::schedule(ptr);
+        }
+    }
+
+    unsafe fn wake_by_ref(ptr: *const ()) {
+        wake(ptr)
+    }
+
+    fn drop(_: *const ()) {}
+
+    // Restores the future from the pointer and polls it. If the future is
+    // pending, we leave it on the heap. If it's ready, we deallocate the
+    // pointer.
+    pub(super) unsafe fn poll(ptr: *const ()) {
         let boxed_future_ptr_ptr = Box::from_raw(ptr as *mut FuturePtr);
         let future_ptr: FuturePtr = *boxed_future_ptr_ptr;
         let boxed_future = Box::from_raw(future_ptr);
         let mut pinned_future = Pin::new_unchecked(&mut *future_ptr);
-        if !super::CLEANUP.load(Ordering::Relaxed)
-            && pinned_future
-                .as_mut()
-                .poll(&mut Context::from_waker(&waker::waker(ptr)))
-                .is_pending()
+        if pinned_future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker::waker(ptr)))
+            .is_pending()
         {
             Box::into_raw(boxed_future_ptr_ptr);
             Box::into_raw(boxed_future);
+        } else {
+            super::unschedule(ptr);
         }
     }
 
-    fn wake_by_ref(_: *const ()) {}
-
-    fn drop(_: *const ()) {}
-
     pub fn waker(ptr: *const ()) -> Waker {
         unsafe { Waker::from_raw(raw_waker(ptr)) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// A future which never completes and records when it is dropped.
+    struct Never<'a>(&'a Cell<bool>);
+
+    impl Future for Never<'_> {
+        type Output = u32;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<u32> {
+            Poll::Pending
+        }
+    }
+
+    impl Drop for Never<'_> {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    #[test]
+    fn select_drops_other_futures() {
+        let result = Rc::new(Cell::new(0));
+        let result_clone = result.clone();
+        spawn(async move {
+            let dropped = Cell::new(false);
+            let output = crate::ic_select! {
+                n = Never(&dropped) => n,
+                n = async { 42 } => n + 1,
+            };
+            assert!(dropped.get());
+            result_clone.set(output);
+        });
+        assert_eq!(result.get(), 43);
+    }
+
+    #[test]
+    fn select_handlers_apply_to_the_enclosing_function() {
+        fn check(n: u32) -> Result<u32, String> {
+            if n > 10 {
+                Err("large".to_string())
+            } else {
+                Ok(n)
+            }
+        }
+
+        async fn increment(n: u32, dropped: &Cell<bool>) -> Result<u32, String> {
+            let output = crate::ic_select! {
+                m = Never(dropped) => m,
+                m = async move { n } => {
+                    if m == 0 {
+                        return Err("zero".to_string());
+                    }
+                    check(m)?
+                },
+            };
+            Ok(output + 1)
+        }
+
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let output = results.clone();
+        spawn(async move {
+            let dropped = Cell::new(false);
+            for n in [0, 20, 2] {
+                let result = increment(n, &dropped).await;
+                output.borrow_mut().push(result);
+            }
+            assert!(dropped.get());
+        });
+        assert_eq!(
+            *results.borrow(),
+            vec![Err("zero".to_string()), Err("large".to_string()), Ok(3)]
+        );
+    }
+
+    #[test]
+    fn panicking_task_does_not_affect_others() {
+        let failed = spawn_isolated(async { panic!("boom") });
+        let completed = spawn_isolated(async {});
+
+        assert_eq!(failed.status(), TaskStatus::Panicked("boom".to_string()));
+        assert_eq!(completed.status(), TaskStatus::Completed);
+    }
+
+    #[test]
+    fn panicking_task_clears_the_queue() {
+        let queued_ran = Rc::new(Cell::new(false));
+        let queued = queued_ran.clone();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            spawn(async move {
+                spawn(async move { queued.set(true) });
+                panic!("boom");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(READY.with(|ready| ready.borrow().is_empty()));
+        assert!(!queued_ran.get());
+
+        let ran = Rc::new(Cell::new(false));
+        let task = ran.clone();
+        spawn(async move { task.set(true) });
+        assert!(ran.get());
+    }
+
+    /// A future which never completes and keeps the waker it was last
+    /// polled with.
+    struct Park(Rc<RefCell<Option<Waker>>>);
+
+    impl Future for Park {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+            *self.0.borrow_mut() = Some(context.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn dropped_task_is_aborted() {
+        let waker = Rc::new(RefCell::new(None));
+        let handle = spawn_isolated(Park(waker.clone()));
+        assert_eq!(handle.status(), TaskStatus::Running);
+
+        let waker = waker.borrow_mut().take().unwrap();
+        wake_for_cleanup(waker);
+        assert_eq!(handle.status(), TaskStatus::Aborted);
+    }
+
+    /// A future which asks to be polled again once, letting the other
+    /// ready tasks run in between.
+    #[derive(Default)]
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            context.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn competing_tasks_make_progress() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let task = |name: &'static str, log: Rc<RefCell<Vec<String>>>| async move {
+            for i in 0..3 {
+                log.borrow_mut().push(format!("{}{}", name, i));
+                YieldNow::default().await;
+            }
+        };
+        let (a, b) = (task("a", log.clone()), task("b", log.clone()));
+        spawn(async move {
+            spawn(a);
+            spawn(b);
+        });
+        assert_eq!(*log.borrow(), vec!["a0", "b0", "a1", "b1", "a2", "b2"]);
+    }
+
+    #[test]
+    fn woken_tasks_run_before_spawned_ones() {
+        /// Wakes itself, then spawns a task, on the first poll.
+        struct WakeThenSpawn {
+            log: Rc<RefCell<Vec<&'static str>>>,
+            polled: bool,
+        }
+
+        impl Future for WakeThenSpawn {
+            type Output = ();
+
+            fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+                if self.polled {
+                    self.log.borrow_mut().push("woken");
+                    return Poll::Ready(());
+                }
+                self.polled = true;
+                context.waker().wake_by_ref();
+                let log = self.log.clone();
+                spawn(async move { log.borrow_mut().push("spawned") });
+                Poll::Pending
+            }
+        }
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        spawn(WakeThenSpawn {
+            log: log.clone(),
+            polled: false,
+        });
+        assert_eq!(*log.borrow(), vec!["woken", "spawned"]);
+    }
+}
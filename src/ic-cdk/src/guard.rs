@@ -0,0 +1,109 @@
+/// A guard which runs a closure when it goes out of scope.
+///
+/// Returned by [defer].
+#[must_use = "the closure runs as soon as the guard is dropped"]
+pub struct Deferred<F: FnOnce()> {
+    f: Option<F>,
+}
+
+impl<F: FnOnce()> Deferred<F> {
+    /// Disarms the guard, so that the closure never runs.
+    pub fn cancel(mut self) {
+        self.f = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for Deferred<F> {
+    fn drop(&mut self) {
+        if let Some(f) = self.f.take() {
+            f();
+        }
+    }
+}
+
+/// Runs `f` when the returned guard is dropped.
+///
+/// Within an async handler, the guard is dropped either when the handler
+/// returns, or when the system runs the cleanup callback of a call awaited
+/// by the handler after one of its reply callbacks trapped. In the latter
+/// case, the task is dropped without being polled again, so `f` still runs
+/// and can release resources acquired before the call:
+///
+/// ```rust,no_run
+/// # use std::cell::Cell;
+/// # thread_local! { static PROCESSING: Cell<bool> = Cell::new(false); }
+/// # async fn do_work() {}
+/// async fn handler() {
+///     PROCESSING.with(|p| p.set(true));
+///     let _guard = ic_cdk::defer(|| PROCESSING.with(|p| p.set(false)));
+///     do_work().await;
+/// }
+/// ```
+///
+/// Changes made during the message execution which traps are rolled back by
+/// the system, whether the guard ran or not. During a cleanup callback, `f`
+/// must not call the system APIs which are not allowed there, such as
+/// replying or making calls.
+pub fn defer<F: FnOnce()>(f: F) -> Deferred<F> {
+    Deferred { f: Some(f) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::futures::{spawn, wake_for_cleanup};
+    use std::cell::{Cell, RefCell};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll, Waker};
+
+    /// A future which never completes and hands out its waker.
+    struct Pending(Rc<RefCell<Option<Waker>>>);
+
+    impl Future for Pending {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            *self.0.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn runs_on_return() {
+        let released = Cell::new(false);
+        {
+            let _guard = defer(|| released.set(true));
+            assert!(!released.get());
+        }
+        assert!(released.get());
+    }
+
+    #[test]
+    fn cancelled_guard_does_not_run() {
+        let released = Cell::new(false);
+        defer(|| released.set(true)).cancel();
+        assert!(!released.get());
+    }
+
+    #[test]
+    fn runs_on_cleanup_after_trap() {
+        let processing = Rc::new(Cell::new(false));
+        let waker = Rc::new(RefCell::new(None));
+
+        let (flag, pending) = (processing.clone(), Pending(waker.clone()));
+        spawn(async move {
+            flag.set(true);
+            let _guard = defer(|| flag.set(false));
+            pending.await;
+            unreachable!("the task is dropped by the cleanup callback");
+        });
+        assert!(processing.get());
+
+        // This is what the cleanup callback does after the reply callback
+        // of the awaited call trapped.
+        wake_for_cleanup(waker.borrow_mut().take().unwrap());
+        assert!(!processing.get());
+    }
+}
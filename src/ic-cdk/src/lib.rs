@@ -9,14 +9,22 @@
 #[cfg(target_feature = "atomics")]
 compile_error!("This version of the CDK does not support multithreading.");
 
+mod allocator;
 pub mod api;
+pub mod candid_service;
 mod futures;
+mod guard;
+mod once_cell;
 mod printer;
 pub mod storage;
 
+pub use allocator::{heap_bytes_in_use, CountingAllocator};
 pub use api::call::call;
 pub use api::call::notify;
 pub use api::{caller, id, print, trap};
+pub use futures::{TaskHandle, TaskStatus};
+pub use guard::{defer, Deferred};
+pub use once_cell::AsyncOnceCell;
 
 static mut DONE: bool = false;
 
@@ -53,6 +61,97 @@ pub fn spawn<F: 'static + std::future::Future<Output = ()>>(future: F) {
     futures::spawn(future);
 }
 
+/// Spawn an asynchronous task whose panics don't propagate to the caller.
+///
+/// If polling the future panics, the task is dropped and its [TaskHandle]
+/// reports [TaskStatus::Panicked], while other tasks keep running.
+///
+/// # Limits
+///
+/// Catching a panic requires unwinding, which is not available when
+/// compiling to `wasm32-unknown-unknown` (canisters are usually built with
+/// `panic = "abort"`). On the IC, a panic traps the current message
+/// execution instead, which rolls back all its state changes. Since every
+/// reply callback runs as a separate message execution, a task panicking
+/// after an `.await` only rolls back that callback, and the system cleanup
+/// callback drops the task without affecting other tasks waiting for their
+/// own calls. Tasks polled in the same message execution as the panicking
+/// one are rolled back together with it. The [TaskHandle] of a task dropped
+/// this way reports [TaskStatus::Aborted].
+pub fn spawn_isolated<F: 'static + std::future::Future<Output = ()>>(future: F) -> TaskHandle {
+    futures::spawn_isolated(future)
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::futures::{Branch, SelectFirst};
+}
+
+/// Awaits multiple futures, and evaluates the handler of the first one to
+/// complete.
+///
+/// Each branch has the form `pattern = future => handler`. All the futures
+/// are created before any of them is polled, so all the calls are sent. Once
+/// a future completes, its output is bound to the pattern and the handler is
+/// evaluated to produce the result of the macro. The other futures are
+/// dropped: their replies are still received, but ignored.
+///
+/// All the handlers must have the same type. Handlers are evaluated once the
+/// futures were dropped, so like in `futures::select!`, `return` and `?` in a
+/// handler apply to the enclosing function.
+///
+/// ```no_run
+/// # async fn example(a: ic_cdk::export::Principal, b: ic_cdk::export::Principal) {
+/// use ic_cdk::api::call::{call, CallResult};
+///
+/// let fastest: CallResult<u64> = ic_cdk::ic_select! {
+///     r = call::<_, (u64,)>(a, "get", ()) => r.map(|(n,)| n),
+///     r = call::<_, (u64,)>(b, "get", ()) => r.map(|(n,)| n),
+/// };
+/// # }
+/// ```
+#[macro_export]
+macro_rules! ic_select {
+    // The future of the `i`-th branch resolves to its output wrapped in
+    // `Branch::This` and `i` `Branch::Next`, so that the futures have the same
+    // type whatever the types of their outputs.
+    (@futures [$($futures:tt)*] $wrap:expr; $fut:expr, $($rest:tt)*) => {
+        $crate::ic_select!(
+            @futures [$($futures)* {
+                let fut = $fut;
+                let wrap = $wrap;
+                $crate::__private::SelectFirst::boxed(async move {
+                    wrap($crate::__private::Branch::This(fut.await))
+                })
+            },]
+            |x| ($wrap)($crate::__private::Branch::Next(x));
+            $($rest)*
+        )
+    };
+    (@futures [$($futures:tt)*] $wrap:expr;) => {
+        ::std::vec![$($futures)*]
+    };
+    (@match $output:ident; $pat:pat => $handler:expr, $($rest:tt)*) => {
+        match $output {
+            $crate::__private::Branch::This($pat) => $handler,
+            $crate::__private::Branch::Next($output) => $crate::ic_select!(@match $output; $($rest)*),
+        }
+    };
+    (@match $output:ident;) => {{
+        let output: ::std::convert::Infallible = $output;
+        match output {}
+    }};
+    ($($pat:pat = $fut:expr => $handler:expr),+ $(,)?) => {
+        match $crate::__private::SelectFirst::new(
+            $crate::ic_select!(@futures [] |x| x; $($fut,)+)
+        )
+        .await
+        {
+            output => $crate::ic_select!(@match output; $($pat => $handler,)+),
+        }
+    };
+}
+
 /// Format and then print the formatted message
 #[cfg(target_arch = "wasm32")]
 #[macro_export]
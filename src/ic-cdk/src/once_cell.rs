@@ -0,0 +1,353 @@
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+// The waker of a waiting caller, emptied if the caller stops waiting.
+type WaiterSlot = Rc<Cell<Option<Waker>>>;
+
+enum State<T> {
+    Empty {
+        initializing: bool,
+        waiters: Vec<WaiterSlot>,
+    },
+    Ready(T),
+}
+
+/// A cell initialized once by an async function, for lazy async singletons.
+///
+/// When several messages interleave, only the first caller of
+/// [get_or_init](AsyncOnceCell::get_or_init) runs the initializer, and the
+/// other callers wait for it to complete instead of running it again.
+///
+/// The cell is a cheap handle to shared state, so it can be stored in a
+/// `thread_local!` and cloned out of it before being awaited:
+///
+/// ```rust,no_run
+/// use ic_cdk::AsyncOnceCell;
+/// # async fn fetch_public_key() -> Vec<u8> { vec![] }
+///
+/// thread_local! {
+///     static PUBLIC_KEY: AsyncOnceCell<Vec<u8>> = AsyncOnceCell::new();
+/// }
+///
+/// async fn public_key() -> Vec<u8> {
+///     let cell = PUBLIC_KEY.with(|cell| cell.clone());
+///     cell.get_or_init(fetch_public_key).await
+/// }
+/// ```
+///
+/// If the task running the initializer is dropped before completing, for
+/// instance by the cleanup callback of a call whose reply callback trapped,
+/// the cell becomes empty again and one of the waiting callers runs its own
+/// initializer. Tasks can't run within a cleanup callback, so in that case
+/// the waiting callers are woken in the next message which runs a task, such
+/// as any `async` canister method.
+pub struct AsyncOnceCell<T> {
+    state: Rc<RefCell<State<T>>>,
+}
+
+impl<T> Clone for AsyncOnceCell<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Rc::clone(&self.state),
+        }
+    }
+}
+
+impl<T> Default for AsyncOnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> AsyncOnceCell<T> {
+    /// Creates an empty cell.
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(State::Empty {
+                initializing: false,
+                waiters: Vec::new(),
+            })),
+        }
+    }
+}
+
+impl<T: Clone> AsyncOnceCell<T> {
+    /// Returns the value of the cell, if it's initialized.
+    pub fn get(&self) -> Option<T> {
+        match &*self.state.borrow() {
+            State::Ready(value) => Some(value.clone()),
+            State::Empty { .. } => None,
+        }
+    }
+
+    /// Returns the value of the cell, initializing it with `init` if it's
+    /// empty.
+    ///
+    /// If another caller is already initializing the cell, waits for it
+    /// instead of calling `init`.
+    pub async fn get_or_init<F, Fut>(&self, init: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        loop {
+            match &mut *self.state.borrow_mut() {
+                State::Ready(value) => return value.clone(),
+                State::Empty { initializing, .. } if !*initializing => {
+                    *initializing = true;
+                    break;
+                }
+                State::Empty { .. } => {}
+            }
+            Wait {
+                state: Rc::clone(&self.state),
+                slot: None,
+            }
+            .await;
+        }
+
+        let mut guard = InitGuard {
+            state: Rc::clone(&self.state),
+            completed: false,
+        };
+        let value = init().await;
+        guard.completed = true;
+        let previous = self.state.replace(State::Ready(value.clone()));
+        if let State::Empty { waiters, .. } = previous {
+            // The state is no longer borrowed, so the waiters can be polled.
+            wake_all(waiters);
+        }
+        value
+    }
+}
+
+/// Waits until the caller running the initializer completes or is dropped.
+struct Wait<T> {
+    state: Rc<RefCell<State<T>>>,
+    slot: Option<WaiterSlot>,
+}
+
+impl<T> Future for Wait<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.slot.is_some() {
+            return Poll::Ready(());
+        }
+        let slot = Rc::new(Cell::new(Some(cx.waker().clone())));
+        if let State::Empty {
+            initializing: true,
+            waiters,
+        } = &mut *self.state.borrow_mut()
+        {
+            waiters.push(Rc::clone(&slot));
+        } else {
+            return Poll::Ready(());
+        }
+        self.slot = Some(slot);
+        Poll::Pending
+    }
+}
+
+/// A waiting caller can be dropped, e.g. by [ic_select](crate::ic_select)
+/// or along with its task, before the initializer completes. Its waker is
+/// removed so that the initializer doesn't wake a task which may be gone.
+impl<T> Drop for Wait<T> {
+    fn drop(&mut self) {
+        if let Some(slot) = &self.slot {
+            slot.take();
+        }
+    }
+}
+
+fn wake_all(waiters: Vec<WaiterSlot>) {
+    waiters
+        .into_iter()
+        .filter_map(|slot| slot.take())
+        .for_each(Waker::wake);
+}
+
+/// Empties the cell again if the initializer is dropped before completing.
+struct InitGuard<T> {
+    state: Rc<RefCell<State<T>>>,
+    completed: bool,
+}
+
+impl<T> Drop for InitGuard<T> {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        match &mut *self.state.borrow_mut() {
+            State::Empty { initializing, .. } => *initializing = false,
+            State::Ready(_) => return,
+        }
+        if crate::futures::in_cleanup() {
+            // Waking the waiters now would drop them as well.
+            let state = Rc::clone(&self.state);
+            crate::futures::after_cleanup(move || wake_waiters(&state));
+        } else {
+            wake_waiters(&self.state);
+        }
+    }
+}
+
+// Wakes the callers waiting for an initializer which was dropped, so that
+// one of them runs its own.
+fn wake_waiters<T>(state: &RefCell<State<T>>) {
+    let waiters = match &mut *state.borrow_mut() {
+        State::Empty { waiters, .. } => std::mem::take(waiters),
+        State::Ready(_) => return,
+    };
+    wake_all(waiters);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::futures::{spawn, wake_for_cleanup};
+    use std::cell::Cell;
+
+    /// A future which completes once opened.
+    #[derive(Clone, Default)]
+    struct Gate {
+        open: Rc<Cell<bool>>,
+        waker: Rc<RefCell<Option<Waker>>>,
+    }
+
+    impl Gate {
+        fn opened() -> Self {
+            let gate = Self::default();
+            gate.open.set(true);
+            gate
+        }
+
+        fn open(&self) {
+            self.open.set(true);
+            let waker = self.waker.borrow_mut().take();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    impl Future for Gate {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.open.get() {
+                Poll::Ready(())
+            } else {
+                *self.waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn spawn_get(
+        cell: &AsyncOnceCell<u32>,
+        gate: &Gate,
+        value: u32,
+        calls: &Rc<Cell<u32>>,
+    ) -> Rc<Cell<Option<u32>>> {
+        let result = Rc::new(Cell::new(None));
+        let (cell, gate, calls, result_clone) =
+            (cell.clone(), gate.clone(), calls.clone(), result.clone());
+        spawn(async move {
+            let value = cell
+                .get_or_init(|| async move {
+                    calls.set(calls.get() + 1);
+                    gate.await;
+                    value
+                })
+                .await;
+            result_clone.set(Some(value));
+        });
+        result
+    }
+
+    #[test]
+    fn initializes_once_under_interleaving() {
+        let cell = AsyncOnceCell::new();
+        let (gate, calls) = (Gate::default(), Rc::new(Cell::new(0)));
+
+        let first = spawn_get(&cell, &gate, 1, &calls);
+        let second = spawn_get(&cell, &Gate::default(), 2, &calls);
+        assert_eq!((first.get(), second.get()), (None, None));
+
+        gate.open();
+        assert_eq!((first.get(), second.get()), (Some(1), Some(1)));
+        assert_eq!(calls.get(), 1);
+        assert_eq!(cell.get(), Some(1));
+        assert_eq!(spawn_get(&cell, &gate, 3, &calls).get(), Some(1));
+    }
+
+    #[test]
+    fn dropped_initializer_empties_the_cell() {
+        let cell = AsyncOnceCell::new();
+        let (first_gate, second_gate) = (Gate::default(), Gate::default());
+        let calls = Rc::new(Cell::new(0));
+
+        let first = spawn_get(&cell, &first_gate, 1, &calls);
+        let second = spawn_get(&cell, &second_gate, 2, &calls);
+
+        // The cleanup callback drops the task running the initializer.
+        let waker = first_gate.waker.borrow_mut().take().unwrap();
+        wake_for_cleanup(waker);
+        assert_eq!(first.get(), None);
+        assert_eq!(cell.get(), None);
+        assert_eq!(calls.get(), 1);
+
+        // The next message running a task wakes the waiting caller, which
+        // runs its own initializer.
+        spawn(async {});
+        assert_eq!(calls.get(), 2);
+        second_gate.open();
+        assert_eq!(second.get(), Some(2));
+        assert_eq!(cell.get(), Some(2));
+    }
+
+    #[test]
+    fn dropped_waiter_is_not_woken() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        struct CountingWaker(AtomicUsize);
+
+        impl std::task::Wake for CountingWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let cell = AsyncOnceCell::<u32>::new();
+        *cell.state.borrow_mut() = State::Empty {
+            initializing: true,
+            waiters: Vec::new(),
+        };
+        let wait = || {
+            let mut wait = Wait {
+                state: Rc::clone(&cell.state),
+                slot: None,
+            };
+            let poll = Pin::new(&mut wait).poll(&mut Context::from_waker(&waker));
+            assert!(poll.is_pending());
+            wait
+        };
+
+        let _waiting = wait();
+        drop(wait());
+        let waiters = match &mut *cell.state.borrow_mut() {
+            State::Empty { waiters, .. } => std::mem::take(waiters),
+            State::Ready(_) => unreachable!(),
+        };
+        assert_eq!(waiters.len(), 2);
+        wake_all(waiters);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+    }
+}
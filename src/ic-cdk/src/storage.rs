@@ -1,5 +1,7 @@
 //! Tools for managing stable storage of data in a canister.
 use crate::api::stable;
+use std::fmt;
+use std::io::Write;
 
 /// Saves the storage into the stable memory.
 ///
@@ -11,6 +13,44 @@ where
     candid::write_args(&mut stable::StableWriter::default(), t)
 }
 
+/// Like [stable_save], but in debug builds traps if the saved value can't be
+/// decoded as `D`, the type passed to [stable_restore] in `post_upgrade`.
+///
+/// This surfaces a value which can't be restored in `pre_upgrade`, where the
+/// upgrade can still be aborted, instead of in `post_upgrade`. Release builds
+/// skip the check.
+///
+/// ```rust,no_run
+/// # use ic_cdk::storage::stable_save_checked;
+/// # use std::collections::BTreeMap;
+/// # let state = BTreeMap::<String, u64>::new();
+/// stable_save_checked::<_, (BTreeMap<String, u64>,)>((&state,)).unwrap();
+/// ```
+pub fn stable_save_checked<T, D>(t: T) -> Result<(), candid::Error>
+where
+    T: candid::utils::ArgumentEncoder,
+    D: for<'de> candid::utils::ArgumentDecoder<'de>,
+{
+    let bytes = candid::encode_args(t)?;
+    if cfg!(debug_assertions) {
+        if let Err(e) = check_roundtrip::<D>(&bytes) {
+            crate::trap(&e);
+        }
+    }
+    stable::StableWriter::default()
+        .write_all(&bytes)
+        .map_err(candid::Error::msg)
+}
+
+fn check_roundtrip<D>(bytes: &[u8]) -> Result<(), String>
+where
+    D: for<'de> candid::utils::ArgumentDecoder<'de>,
+{
+    candid::decode_args::<D>(bytes)
+        .map(|_| ())
+        .map_err(|e| format!("the saved value can't be restored: {:?}", e))
+}
+
 /// Restores a value from the stable memory to the storage.
 ///
 /// There can only be one value in stable memory, currently.
@@ -25,3 +65,224 @@ where
     let res = candid::utils::ArgumentDecoder::decode(&mut de).map_err(|e| format!("{:?}", e))?;
     Ok(res)
 }
+
+// The header written by `stable_save_with_checksum`: the magic number, the
+// length of the payload as a little-endian u64 and the CRC-32 of the payload
+// as a little-endian u32.
+const MAGIC: &[u8; 4] = b"CDKS";
+pub(crate) const HEADER_LEN: usize = 16;
+
+/// Why the stable memory doesn't hold a value saved with
+/// [stable_save_with_checksum].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StableCorruption {
+    /// The stable memory doesn't start with the expected magic number: it
+    /// is empty, or was written by something else.
+    MissingHeader,
+    /// The payload extends past the end of the stable memory.
+    Truncated {
+        /// The length of the payload according to the header.
+        len: u64,
+        /// The number of bytes available after the header.
+        available: u64,
+    },
+    /// The checksum of the payload doesn't match the header.
+    ChecksumMismatch {
+        /// The checksum in the header.
+        expected: u32,
+        /// The checksum of the payload.
+        actual: u32,
+    },
+}
+
+impl fmt::Display for StableCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeader => f.write_str("the stable memory has no checksummed value"),
+            Self::Truncated { len, available } => write!(
+                f,
+                "the stable memory holds {} bytes after the header, but the value takes {}",
+                available, len
+            ),
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "the checksum of the stable memory is {:08x} instead of {:08x}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StableCorruption {}
+
+/// An error restoring a value with [stable_restore_with_checksum].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StableRestoreError {
+    /// The stable memory is corrupted or wasn't written by
+    /// [stable_save_with_checksum].
+    Corrupted(StableCorruption),
+    /// The stable memory is intact, but the value doesn't decode as the
+    /// expected type, e.g. because its type changed in an incompatible way.
+    Decode(String),
+}
+
+impl fmt::Display for StableRestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Corrupted(e) => write!(f, "corrupted stable memory: {}", e),
+            Self::Decode(e) => write!(f, "failed to decode the stable memory: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StableRestoreError {}
+
+impl From<StableCorruption> for StableRestoreError {
+    fn from(e: StableCorruption) -> Self {
+        Self::Corrupted(e)
+    }
+}
+
+/// Like [stable_save], but writes a header with a magic number and a
+/// checksum before the value.
+///
+/// Restore the value with [stable_restore_with_checksum], which tells
+/// corrupted or foreign stable memory apart from a value which doesn't
+/// decode as the expected type.
+pub fn stable_save_with_checksum<T>(t: T) -> Result<(), candid::Error>
+where
+    T: candid::utils::ArgumentEncoder,
+{
+    let bytes = candid::encode_args(t)?;
+    let mut writer = stable::StableWriter::default();
+    writer
+        .write_all(&header(&bytes))
+        .and_then(|()| writer.write_all(&bytes))
+        .map_err(candid::Error::msg)
+}
+
+/// Restores a value saved with [stable_save_with_checksum].
+///
+/// Only the bytes of the value are read from the stable memory, and their
+/// checksum is verified before they are decoded.
+pub fn stable_restore_with_checksum<T>() -> Result<T, StableRestoreError>
+where
+    T: for<'de> candid::utils::ArgumentDecoder<'de>,
+{
+    let size = stable::stable64_size() << 16;
+    if size < HEADER_LEN as u64 {
+        return Err(StableCorruption::MissingHeader.into());
+    }
+    let mut header = [0; HEADER_LEN];
+    stable::stable64_read(0, &mut header);
+    let (len, checksum) = parse_header(&header, size - HEADER_LEN as u64)?;
+    let mut bytes = vec![0; len as usize];
+    stable::stable64_read(HEADER_LEN as u64, &mut bytes);
+    verify_checksum(&bytes, checksum)?;
+    candid::decode_args(&bytes).map_err(|e| StableRestoreError::Decode(format!("{:?}", e)))
+}
+
+fn header(payload: &[u8]) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(MAGIC);
+    header[4..12].copy_from_slice(&(payload.len() as u64).to_le_bytes());
+    header[12..].copy_from_slice(&crc32(payload).to_le_bytes());
+    header
+}
+
+// Returns the length and the checksum of the payload.
+pub(crate) fn parse_header(
+    header: &[u8; HEADER_LEN],
+    available: u64,
+) -> Result<(u64, u32), StableCorruption> {
+    if &header[..4] != MAGIC {
+        return Err(StableCorruption::MissingHeader);
+    }
+    let mut len = [0; 8];
+    len.copy_from_slice(&header[4..12]);
+    let len = u64::from_le_bytes(len);
+    if len > available {
+        return Err(StableCorruption::Truncated { len, available });
+    }
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&header[12..]);
+    Ok((len, u32::from_le_bytes(checksum)))
+}
+
+fn verify_checksum(payload: &[u8], expected: u32) -> Result<(), StableCorruption> {
+    let actual = crc32(payload);
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(StableCorruption::ChecksumMismatch { expected, actual })
+    }
+}
+
+// CRC-32 (IEEE), as used by gzip and zip.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_check() {
+        let bytes = candid::encode_args((vec![1u64, 2, 3], "value")).unwrap();
+        assert_eq!(check_roundtrip::<(Vec<u64>, String)>(&bytes), Ok(()));
+        let err = check_roundtrip::<(Vec<String>, String)>(&bytes).unwrap_err();
+        assert!(err.starts_with("the saved value can't be restored"));
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        let payload = candid::encode_args(("value", 42u64)).unwrap();
+        let header = header(&payload);
+        let (len, checksum) = parse_header(&header, payload.len() as u64).unwrap();
+        assert_eq!(len, payload.len() as u64);
+        assert_eq!(verify_checksum(&payload, checksum), Ok(()));
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let payload = candid::encode_args(("value", 42u64)).unwrap();
+        let header = header(&payload);
+
+        assert_eq!(
+            parse_header(&[0; HEADER_LEN], 100),
+            Err(StableCorruption::MissingHeader)
+        );
+        assert_eq!(
+            parse_header(&header, 3),
+            Err(StableCorruption::Truncated {
+                len: payload.len() as u64,
+                available: 3
+            })
+        );
+
+        let (_, checksum) = parse_header(&header, payload.len() as u64).unwrap();
+        let mut corrupted = payload.clone();
+        corrupted[5] ^= 1;
+        assert!(matches!(
+            verify_checksum(&corrupted, checksum),
+            Err(StableCorruption::ChecksumMismatch { .. })
+        ));
+    }
+}
@@ -23,6 +23,8 @@ extern "C" {
     pub fn canister_cycle_balance() -> i64;
     pub fn canister_cycle_balance128(dst: i32);
     pub fn canister_status() -> i32;
+    pub fn subnet_self_size() -> i32;
+    pub fn subnet_self_copy(dst: i32, offset: i32, size: i32);
     pub fn msg_method_name_size() -> i32;
     pub fn msg_method_name_copy(dst: i32, offset: i32, size: i32);
     pub fn accept_message();